@@ -9,6 +9,7 @@
 //! - **bezier**: Quadratic Bezier curves for energy flow visualization
 //! - **clustering**: Energy profile archetype clustering
 //! - **crypto**: SHA-256 and HMAC-SHA256 cryptographic operations
+//! - **decimal**: Fixed-point decimal type for exact price and quantity accounting
 //! - **governance**: Solana governance client with ZK-weighted voting
 //! - **orderbook**: Order matching engine with depth chart
 //! - **portfolio**: Aggregated portfolio risk analytics
@@ -23,6 +24,7 @@ pub use modules::auction::*;
 pub use modules::bezier::*;
 pub use modules::clustering::*;
 pub use modules::crypto::*;
+pub use modules::decimal::*;
 pub use modules::governance::*;
 pub use modules::orderbook::*;
 pub use modules::portfolio::*;
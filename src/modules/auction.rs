@@ -3,24 +3,83 @@ use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use wasm_bindgen::prelude::*;
 
+mod blocks;
+mod clearing;
+mod curves;
+mod day_ahead;
+mod settlement;
+mod zones;
+
+pub use blocks::{
+    BlockClearing, BlockOrder, BlockPeriod, BlockResult, BlockStatus, PeriodClearing,
+};
+pub use clearing::{ClearingResult, TieBreak};
+pub use curves::{AuctionCurves, CurveIntersection, CurveStep, OrderAllocation};
+pub use day_ahead::{
+    CurveOrder, CurvePoint, DayAheadClearing, DayAheadPeriod, Interpolation, OrderFill,
+    PeriodCurve, MAX_PERIODS,
+};
+pub use settlement::{ParticipantSettlement, PricingRule, Settlement};
+pub use zones::{Interconnector, InterconnectorFlow, ZonalClearing, ZoneClearing};
+
+// Tolerance for comparing summed f64 volumes, surpluses and path costs
+const EPSILON: f64 = 1e-9;
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 pub struct AuctionOrderWasm {
     pub id: u32,
     pub price: f64,
     pub amount: f64,
     pub is_bid: bool,
+    /// Delivery period; single-period clearing uses period 0
+    #[serde(default)]
+    pub period: u32,
+    /// Grid zone; only zonal clearing tells zones apart
+    #[serde(default)]
+    pub zone: u32,
+    /// Who settles the order; defaults to the order id
+    #[serde(default)]
+    pub participant: Option<u32>,
+}
+
+impl AuctionOrderWasm {
+    fn participant(&self) -> u32 {
+        self.participant.unwrap_or(self.id)
+    }
 }
 
 #[wasm_bindgen]
 pub struct AuctionSimulator {
     orders: Vec<AuctionOrderWasm>,
+    // All-or-none and curtailable orders spanning several periods
+    blocks: Vec<BlockOrder>,
+    // Per-period price/amount curves for day-ahead clearing
+    curve_orders: Vec<CurveOrder>,
+    // One-way transfer capacities between zones; kept across `clear`
+    interconnectors: Vec<Interconnector>,
+    // Rounding for pro-rata fills (0 = none) and who gets leftover lots
+    lot_size: f64,
+    tie_break: TieBreak,
+    // Where to settle within a range of equally good prices
+    reference_price: Option<f64>,
+    // How cleared orders pay in `settle`
+    pricing_rule: PricingRule,
 }
 
 #[wasm_bindgen]
 impl AuctionSimulator {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
-        Self { orders: Vec::new() }
+        Self {
+            orders: Vec::new(),
+            blocks: Vec::new(),
+            curve_orders: Vec::new(),
+            interconnectors: Vec::new(),
+            lot_size: 0.0,
+            tie_break: TieBreak::default(),
+            reference_price: None,
+            pricing_rule: PricingRule::default(),
+        }
     }
 
     pub fn add_order(&mut self, id: u32, price: f64, amount: f64, is_bid: bool) {
@@ -29,77 +88,97 @@ impl AuctionSimulator {
             price,
             amount,
             is_bid,
+            period: 0,
+            zone: 0,
+            participant: None,
         });
     }
 
+    /// Add an order object
+    /// Expects `{ id, price, amount, is_bid, period?, zone?, participant? }`
+    pub fn place_order(&mut self, order: JsValue) -> Result<(), JsValue> {
+        let order: AuctionOrderWasm = serde_wasm_bindgen::from_value(order)?;
+        self.orders.push(order);
+        Ok(())
+    }
+
+    /// Remove all orders, including block orders
     pub fn clear(&mut self) {
         self.orders.clear();
+        self.blocks.clear();
+        self.curve_orders.clear();
     }
 
     /// Calculate Uniform Clearing Price (MCP) - Optimized to O(n log n)
+    /// The price maximises volume, then minimises the leftover surplus and
+    /// follows market pressure (see `clear_auction`).
     /// Returns [clearing_price, clearing_volume]
     pub fn calculate_clearing_price(&self) -> Vec<f64> {
-        let mut bids: Vec<&AuctionOrderWasm> = self.orders.iter().filter(|o| o.is_bid).collect();
-        let mut asks: Vec<&AuctionOrderWasm> = self.orders.iter().filter(|o| !o.is_bid).collect();
-
-        if bids.is_empty() || asks.is_empty() {
-            return vec![0.0, 0.0];
+        let result = self.clearing();
+        match result.price {
+            Some(price) => vec![price, result.volume],
+            None => vec![0.0, 0.0],
         }
+    }
+}
 
-        // Sort Bids DESC by price
-        bids.sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap_or(Ordering::Equal));
-        // Sort Asks ASC by price
-        asks.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap_or(Ordering::Equal));
-
-        // Build cumulative demand curve (descending from highest bid)
-        let mut cum_demand: Vec<(f64, f64)> = Vec::with_capacity(bids.len()); // (price, cumulative_amount)
-        let mut cum_amount = 0.0;
-        for bid in &bids {
-            cum_amount += bid.amount;
-            cum_demand.push((bid.price, cum_amount));
-        }
+impl AuctionSimulator {
+    /// Orders cleared by the single-period entry points
+    fn single_period(&self) -> impl Iterator<Item = &AuctionOrderWasm> {
+        self.orders.iter().filter(|o| o.period == 0)
+    }
+}
 
-        // Build cumulative supply curve (ascending from lowest ask)
-        let mut cum_supply: Vec<(f64, f64)> = Vec::with_capacity(asks.len()); // (price, cumulative_amount)
-        let mut cum_amount = 0.0;
-        for ask in &asks {
-            cum_amount += ask.amount;
-            cum_supply.push((ask.price, cum_amount));
-        }
+/// Bids highest price first and asks lowest first; equal prices keep
+/// entry order
+fn sorted_sides<'a>(
+    orders: impl Iterator<Item = &'a AuctionOrderWasm>,
+) -> (Vec<&'a AuctionOrderWasm>, Vec<&'a AuctionOrderWasm>) {
+    let (mut bids, mut asks): (Vec<&AuctionOrderWasm>, Vec<_>) = orders.partition(|o| o.is_bid);
+
+    // Sort Bids DESC by price
+    bids.sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap_or(Ordering::Equal));
+    // Sort Asks ASC by price
+    asks.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap_or(Ordering::Equal));
+    (bids, asks)
+}
 
-        // Find maximum intersection using two-pointer technique - O(n)
-        let mut clearing_price = 0.0;
-        let mut max_volume = 0.0;
-        let mut bid_idx = 0;
-        let mut ask_idx = 0;
-
-        while bid_idx < cum_demand.len() && ask_idx < cum_supply.len() {
-            let (bid_price, demand) = cum_demand[bid_idx];
-            let (ask_price, supply) = cum_supply[ask_idx];
-
-            if ask_price <= bid_price {
-                // Potential match at this price level
-                let volume = supply.min(demand);
-
-                if volume > max_volume {
-                    max_volume = volume;
-                    // Use midpoint as clearing price
-                    clearing_price = (ask_price + bid_price) / 2.0;
-                } else if (volume - max_volume).abs() < f64::EPSILON && volume > 0.0 {
-                    // Same volume, update to higher price (producer surplus)
-                    clearing_price = (ask_price + bid_price) / 2.0;
-                }
-
-                // Move to next supply level
-                ask_idx += 1;
-            } else {
-                // Price gap, move to lower demand
-                bid_idx += 1;
-            }
-        }
+impl Default for AuctionSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        vec![clearing_price, max_volume]
+/// Find the demand and supply steps with the largest executable volume,
+/// pairing each demand step with the furthest supply step priced at or below it
+/// Returns `(bid_price, ask_price, volume)` of that crossing, or `None` if
+/// the curves do not cross. Among equal volumes the highest-priced crossing
+/// wins (producer surplus).
+pub(crate) fn intersect<P, Q>(cum_demand: &[(P, Q)], cum_supply: &[(P, Q)]) -> Option<(P, P, Q)>
+where
+    P: Copy + PartialOrd,
+    Q: Copy + Default + PartialOrd,
+{
+    // Two pointers - O(n): the supply reachable by a bid only shrinks as
+    // the bid price falls
+    let mut best: Option<(P, P, Q)> = None;
+    let mut ask_idx = cum_supply.len();
+
+    for &(bid_price, demand) in cum_demand {
+        while ask_idx > 0 && cum_supply[ask_idx - 1].0 > bid_price {
+            ask_idx -= 1;
+        }
+        if ask_idx == 0 {
+            break;
+        }
+        let (ask_price, supply) = cum_supply[ask_idx - 1];
+        let volume = if supply < demand { supply } else { demand };
+        if volume > best.map_or(Q::default(), |(_, _, v)| v) {
+            best = Some((bid_price, ask_price, volume));
+        }
     }
+
+    best
 }
 
 // ============================================================================
@@ -0,0 +1,608 @@
+//! Block Orders
+//!
+//! Day-ahead style blocks: one limit price over an amount profile spanning
+//! several periods, accepted at a single ratio of that profile in every
+//! period. A ratio below 1 is only allowed down to the block's
+//! `min_acceptance_ratio`, so a ratio of 1 makes the block all-or-none. A
+//! linked block names a `parent` and can only be accepted with it; the
+//! parent is judged together with its accepted descendants, so a profitable
+//! child can carry a loss-making parent.
+//!
+//! Block selection is non-convex, so clearing is a heuristic. Every block
+//! starts fully accepted and enters its periods as a price-taking order
+//! next to the period orders. After each round of period clearings one
+//! correction is made. First the period side with the largest shortfall
+//! (block amount its period could not absorb) is taken off the blocks there,
+//! pro rata to their room above their minimum ratios; without enough room,
+//! the block with the least room is rejected with its children. Then the
+//! block with the worst loss per unit at the period prices is curtailed to
+//! its minimum ratio, or rejected with its children. This repeats until
+//! every accepted block is absorbed and in the money. A curtailment removes
+//! its period's whole shortfall, so it only repeats when it moves the
+//! period's price; the rounds are still capped, and a clearing that hit the
+//! cap is reported as not converged. Rejected blocks that would have been
+//! in the money at the final prices, with their parent accepted and a price
+//! in each of their periods, are reported as paradoxically rejected.
+
+use super::{AuctionOrderWasm, AuctionSimulator, DayAheadPeriod, EPSILON};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use wasm_bindgen::prelude::*;
+
+/// Amount a block trades in one period
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockPeriod {
+    pub period: u32,
+    pub amount: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockOrder {
+    pub id: u32,
+    pub is_bid: bool,
+    /// Limit on the block's volume-weighted average price
+    pub price: f64,
+    pub profile: Vec<BlockPeriod>,
+    /// Smallest accepted fraction of the profile; 1 = all-or-none
+    #[serde(default = "all_or_none")]
+    pub min_acceptance_ratio: f64,
+    /// Block this one is linked to
+    #[serde(default)]
+    pub parent: Option<u32>,
+}
+
+fn all_or_none() -> f64 {
+    1.0
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BlockStatus {
+    Accepted,
+    /// Accepted at a ratio below 1
+    Curtailed,
+    Rejected,
+    /// Rejected although it would have been in the money at the final prices
+    ParadoxicallyRejected,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockResult {
+    pub id: u32,
+    pub status: BlockStatus,
+    pub accepted_ratio: f64,
+    /// Surplus at the final prices for the accepted ratio, or for the full
+    /// profile if rejected
+    pub surplus: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeriodClearing {
+    pub period: u32,
+    pub price: Option<f64>,
+    pub volume: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockClearing {
+    /// Every period with orders, curve orders or block amounts, in order
+    pub periods: Vec<PeriodClearing>,
+    /// Blocks in entry order
+    pub blocks: Vec<BlockResult>,
+    pub paradoxically_rejected: Vec<u32>,
+    /// False if block selection stopped at its round limit with a block
+    /// still not absorbed or out of the money
+    pub converged: bool,
+}
+
+/// Outcome of block selection over a set of periods
+pub(super) struct BlockSelection {
+    pub(super) periods: Vec<DayAheadPeriod>,
+    pub(super) blocks: Vec<BlockResult>,
+    pub(super) paradoxically_rejected: Vec<u32>,
+    pub(super) converged: bool,
+}
+
+/// Correction rounds allowed per block
+const ROUNDS_PER_BLOCK: usize = 8;
+
+#[wasm_bindgen]
+impl AuctionSimulator {
+    /// Add a block order
+    /// Expects `{ id, is_bid, price, profile: [{ period, amount }],
+    /// min_acceptance_ratio?, parent? }`. A parent must be added before its
+    /// children.
+    pub fn add_block_order(&mut self, block: JsValue) -> Result<(), JsValue> {
+        let block: BlockOrder = serde_wasm_bindgen::from_value(block)?;
+        self.add_block(block).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Clear every period together with the block orders
+    /// Returns `{ periods: [{ period, price, volume }], blocks: [{ id, status,
+    /// accepted_ratio, surplus }], paradoxically_rejected, converged }`.
+    pub fn clear_blocks(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.block_clearing())?)
+    }
+}
+
+impl AuctionSimulator {
+    pub(super) fn add_block(&mut self, block: BlockOrder) -> Result<(), String> {
+        if self.blocks.iter().any(|b| b.id == block.id) {
+            return Err(format!("Block {} already exists", block.id));
+        }
+        if !block.price.is_finite() {
+            return Err("Block price must be a finite number".to_string());
+        }
+        if block.profile.is_empty()
+            || block
+                .profile
+                .iter()
+                .any(|p| !p.amount.is_finite() || p.amount < 0.0)
+        {
+            return Err("Block profile must have non-negative amounts".to_string());
+        }
+        let ratio = block.min_acceptance_ratio;
+        if !(ratio > 0.0 && ratio <= 1.0) {
+            return Err("min_acceptance_ratio must be in (0, 1]".to_string());
+        }
+        if let Some(parent) = block.parent {
+            if !self.blocks.iter().any(|b| b.id == parent) {
+                return Err(format!("Parent block {} not found", parent));
+            }
+        }
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    pub(super) fn block_clearing(&self) -> BlockClearing {
+        let periods: Vec<u32> = self
+            .orders
+            .iter()
+            .map(|o| o.period)
+            .chain(
+                self.curve_orders
+                    .iter()
+                    .flat_map(|o| o.curves.iter().map(|c| c.period)),
+            )
+            .chain(
+                self.blocks
+                    .iter()
+                    .flat_map(|b| b.profile.iter().map(|p| p.period)),
+            )
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let selection = self.select_blocks(&periods);
+        BlockClearing {
+            periods: selection
+                .periods
+                .into_iter()
+                .map(|p| PeriodClearing {
+                    period: p.period,
+                    price: p.price,
+                    volume: p.volume,
+                })
+                .collect(),
+            blocks: selection.blocks,
+            paradoxically_rejected: selection.paradoxically_rejected,
+            converged: selection.converged,
+        }
+    }
+
+    /// Choose block ratios while clearing `periods`
+    pub(super) fn select_blocks(&self, periods: &[u32]) -> BlockSelection {
+        let mut ratios: Vec<f64> = vec![1.0; self.blocks.len()];
+
+        // Each round changes one ratio, and ratios only fall
+        let mut rounds = 0;
+        let (clearings, converged) = loop {
+            let clearings = self.clear_periods(periods, &ratios);
+            if !self.correct(&clearings, &mut ratios) {
+                break (clearings, true);
+            }
+            rounds += 1;
+            if rounds > ROUNDS_PER_BLOCK * self.blocks.len() {
+                break (self.clear_periods(periods, &ratios), false);
+            }
+        };
+
+        let prices: Vec<(u32, Option<f64>)> = clearings
+            .iter()
+            .map(|c| (c.period.period, c.period.price))
+            .collect();
+        let mut paradoxically_rejected = Vec::new();
+        let blocks = self
+            .blocks
+            .iter()
+            .zip(&ratios)
+            .enumerate()
+            .map(|(i, (block, &ratio))| {
+                let (status, surplus) = if ratio > 0.0 {
+                    let status = if ratio < 1.0 - EPSILON {
+                        BlockStatus::Curtailed
+                    } else {
+                        BlockStatus::Accepted
+                    };
+                    (status, surplus(block, ratio, &prices))
+                } else {
+                    let parent_accepted = block.parent.is_none_or(|parent| {
+                        self.blocks
+                            .iter()
+                            .zip(&ratios)
+                            .any(|(b, &r)| b.id == parent && r > 0.0)
+                    });
+                    // The whole family, as if accepted in full, in periods
+                    // that have a market at all
+                    let full = vec![1.0; self.blocks.len()];
+                    let priced = block.profile.iter().all(|p| {
+                        prices
+                            .iter()
+                            .any(|&(period, price)| period == p.period && price.is_some())
+                    });
+                    if parent_accepted && priced && self.family_surplus(i, &full, &prices) > EPSILON
+                    {
+                        paradoxically_rejected.push(block.id);
+                        (
+                            BlockStatus::ParadoxicallyRejected,
+                            surplus(block, 1.0, &prices),
+                        )
+                    } else {
+                        (BlockStatus::Rejected, surplus(block, 1.0, &prices))
+                    }
+                };
+                BlockResult {
+                    id: block.id,
+                    status,
+                    accepted_ratio: ratio,
+                    surplus,
+                }
+            })
+            .collect();
+
+        BlockSelection {
+            periods: clearings.into_iter().map(|c| c.period).collect(),
+            blocks,
+            paradoxically_rejected,
+            converged,
+        }
+    }
+
+    /// Clear each period with the blocks at `ratios` as price-taking orders
+    fn clear_periods(&self, periods: &[u32], ratios: &[f64]) -> Vec<RoundClearing> {
+        periods
+            .iter()
+            .map(|&period| {
+                let mut orders: Vec<AuctionOrderWasm> = Vec::new();
+                let (mut block_demand, mut block_supply) = (0.0, 0.0);
+                for (block, &ratio) in self.blocks.iter().zip(ratios) {
+                    let amount = ratio * block_amount(block, period);
+                    if amount <= 0.0 {
+                        continue;
+                    }
+                    if block.is_bid {
+                        block_demand += amount;
+                    } else {
+                        block_supply += amount;
+                    }
+                    orders.push(AuctionOrderWasm {
+                        id: block.id,
+                        price: if block.is_bid {
+                            f64::INFINITY
+                        } else {
+                            f64::NEG_INFINITY
+                        },
+                        amount,
+                        is_bid: block.is_bid,
+                        period,
+                        zone: 0,
+                        participant: None,
+                    });
+                }
+
+                let result = self.clear_period(period, &orders);
+                // Price-taking orders fill first, so blocks share any shortfall
+                RoundClearing {
+                    bid_shortfall: (block_demand - result.volume).max(0.0),
+                    ask_shortfall: (block_supply - result.volume).max(0.0),
+                    period: result,
+                }
+            })
+            .collect()
+    }
+
+    /// Curtail or reject the block most in need of it; false once every
+    /// accepted block is absorbed and in the money
+    fn correct(&self, clearings: &[RoundClearing], ratios: &mut [f64]) -> bool {
+        let prices: Vec<(u32, Option<f64>)> = clearings
+            .iter()
+            .map(|c| (c.period.period, c.period.price))
+            .collect();
+        let accepted = || (0..self.blocks.len()).filter(|&i| ratios[i] > 0.0);
+
+        // The period side furthest from absorbing its blocks
+        let worst_shortfall = clearings
+            .iter()
+            .flat_map(|c| {
+                [
+                    (c.period.period, true, c.bid_shortfall),
+                    (c.period.period, false, c.ask_shortfall),
+                ]
+            })
+            .filter(|&(_, _, shortfall)| shortfall > EPSILON)
+            .min_by(|a, b| b.2.total_cmp(&a.2));
+        if let Some((period, is_bid, shortfall)) = worst_shortfall {
+            // Each block's amount there and its room above its minimum ratio
+            let blocks: Vec<(usize, f64, f64)> = accepted()
+                .filter(|&i| self.blocks[i].is_bid == is_bid)
+                .map(|i| (i, block_amount(&self.blocks[i], period)))
+                .filter(|&(_, amount)| amount > 0.0)
+                .map(|(i, amount)| {
+                    let room = (ratios[i] - self.blocks[i].min_acceptance_ratio).max(0.0);
+                    (i, amount, room * amount)
+                })
+                .collect();
+            let room: f64 = blocks.iter().map(|b| b.2).sum();
+            if room >= shortfall - EPSILON {
+                for &(i, amount, own_room) in &blocks {
+                    ratios[i] -= shortfall * own_room / room / amount;
+                }
+            } else {
+                // Later blocks go first among equals
+                let least = blocks
+                    .iter()
+                    .rev()
+                    .min_by(|a, b| a.2.total_cmp(&b.2))
+                    .expect("a shortfall comes from accepted blocks");
+                self.reject(least.0, ratios);
+            }
+            return true;
+        }
+
+        let worst_loss = accepted()
+            .map(|i| {
+                let volume = self.family_volume(i, ratios);
+                (
+                    i,
+                    self.family_surplus(i, ratios, &prices) / volume.max(EPSILON),
+                )
+            })
+            .filter(|&(_, per_unit)| per_unit < -EPSILON)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, _)) = worst_loss {
+            let min_ratio = self.blocks[i].min_acceptance_ratio;
+            if ratios[i] > min_ratio + EPSILON {
+                ratios[i] = min_ratio;
+            } else {
+                self.reject(i, ratios);
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Reject a block and everything linked below it
+    fn reject(&self, i: usize, ratios: &mut [f64]) {
+        ratios[i] = 0.0;
+        let id = self.blocks[i].id;
+        for child in 0..self.blocks.len() {
+            if self.blocks[child].parent == Some(id) && ratios[child] > 0.0 {
+                self.reject(child, ratios);
+            }
+        }
+    }
+
+    /// Surplus of a block and its accepted descendants
+    fn family_surplus(&self, i: usize, ratios: &[f64], prices: &[(u32, Option<f64>)]) -> f64 {
+        let id = self.blocks[i].id;
+        surplus(&self.blocks[i], ratios[i], prices)
+            + (0..self.blocks.len())
+                .filter(|&c| self.blocks[c].parent == Some(id) && ratios[c] > 0.0)
+                .map(|c| self.family_surplus(c, ratios, prices))
+                .sum::<f64>()
+    }
+
+    fn family_volume(&self, i: usize, ratios: &[f64]) -> f64 {
+        let id = self.blocks[i].id;
+        let own: f64 = self.blocks[i].profile.iter().map(|p| p.amount).sum();
+        ratios[i] * own
+            + (0..self.blocks.len())
+                .filter(|&c| self.blocks[c].parent == Some(id) && ratios[c] > 0.0)
+                .map(|c| self.family_volume(c, ratios))
+                .sum::<f64>()
+    }
+}
+
+/// One period's clearing in a selection round, with the block demand and
+/// supply it could not absorb
+struct RoundClearing {
+    period: DayAheadPeriod,
+    bid_shortfall: f64,
+    ask_shortfall: f64,
+}
+
+fn block_amount(block: &BlockOrder, period: u32) -> f64 {
+    block
+        .profile
+        .iter()
+        .filter(|p| p.period == period)
+        .map(|p| p.amount)
+        .sum()
+}
+
+/// Gain from trading `ratio` of the profile at the period prices, against
+/// the block's limit
+fn surplus(block: &BlockOrder, ratio: f64, prices: &[(u32, Option<f64>)]) -> f64 {
+    block
+        .profile
+        .iter()
+        .map(|p| {
+            let price = prices
+                .iter()
+                .find(|(period, _)| *period == p.period)
+                .and_then(|(_, price)| *price)
+                .unwrap_or(block.price);
+            let margin = if block.is_bid {
+                block.price - price
+            } else {
+                price - block.price
+            };
+            ratio * p.amount * margin
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u32, is_bid: bool, price: f64, amounts: &[(u32, f64)]) -> BlockOrder {
+        BlockOrder {
+            id,
+            is_bid,
+            price,
+            profile: amounts
+                .iter()
+                .map(|&(period, amount)| BlockPeriod { period, amount })
+                .collect(),
+            min_acceptance_ratio: 1.0,
+            parent: None,
+        }
+    }
+
+    fn period_order(
+        auction: &mut AuctionSimulator,
+        id: u32,
+        period: u32,
+        price: f64,
+        amount: f64,
+        is_bid: bool,
+    ) {
+        auction.orders.push(AuctionOrderWasm {
+            id,
+            price,
+            amount,
+            is_bid,
+            period,
+            zone: 0,
+            participant: None,
+        });
+    }
+
+    #[test]
+    fn test_loss_making_block_is_rejected_and_reported_when_paradoxical() {
+        let mut auction = AuctionSimulator::new();
+        period_order(&mut auction, 1, 0, 60.0, 15.0, true);
+        period_order(&mut auction, 2, 1, 60.0, 10.0, true);
+        for period in 0..2 {
+            period_order(&mut auction, 3, period, 40.0, 10.0, false);
+        }
+        // Selling 10 in both periods needs 45; while in, it pushes both
+        // prices down to 40
+        auction
+            .add_block(block(10, false, 45.0, &[(0, 10.0), (1, 10.0)]))
+            .unwrap();
+        // Cheap enough to stay in at any price
+        auction
+            .add_block(block(11, false, 10.0, &[(0, 5.0)]))
+            .unwrap();
+
+        let result = auction.block_clearing();
+        assert_eq!(
+            result.periods,
+            vec![
+                PeriodClearing {
+                    period: 0,
+                    price: Some(50.0),
+                    volume: 15.0,
+                },
+                PeriodClearing {
+                    period: 1,
+                    price: Some(50.0),
+                    volume: 10.0,
+                },
+            ]
+        );
+        assert_eq!(
+            result.blocks,
+            vec![
+                BlockResult {
+                    id: 10,
+                    status: BlockStatus::ParadoxicallyRejected,
+                    accepted_ratio: 0.0,
+                    surplus: 100.0,
+                },
+                BlockResult {
+                    id: 11,
+                    status: BlockStatus::Accepted,
+                    accepted_ratio: 1.0,
+                    surplus: 200.0,
+                },
+            ]
+        );
+        assert_eq!(result.paradoxically_rejected, vec![10]);
+    }
+
+    #[test]
+    fn test_curtailable_block_and_linked_children() {
+        let mut auction = AuctionSimulator::new();
+        period_order(&mut auction, 1, 0, 30.0, 6.0, false);
+        // Wants 10 but only 6 is offered; may drop to half
+        let mut curtailable = block(20, true, 40.0, &[(0, 10.0)]);
+        curtailable.min_acceptance_ratio = 0.5;
+        auction.add_block(curtailable).unwrap();
+        // Needs 10 more, which nobody sells: rejected, taking its child along
+        let parent = block(21, true, 40.0, &[(1, 10.0)]);
+        let child = BlockOrder {
+            parent: Some(21),
+            ..block(22, true, 40.0, &[(0, 1.0)])
+        };
+        auction.add_block(parent).unwrap();
+        auction.add_block(child).unwrap();
+        assert!(auction
+            .add_block(BlockOrder {
+                parent: Some(99),
+                ..block(23, true, 40.0, &[(0, 1.0)])
+            })
+            .is_err());
+
+        let result = auction.block_clearing();
+        assert_eq!(result.blocks[0].status, BlockStatus::Curtailed);
+        assert!((result.blocks[0].accepted_ratio - 0.6).abs() < 1e-9);
+        assert_eq!(result.blocks[1].status, BlockStatus::Rejected);
+        assert_eq!(result.blocks[2].status, BlockStatus::Rejected);
+        assert_eq!(result.periods[0].price, Some(30.0));
+        assert!((result.periods[0].volume - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_shared_shortfall_is_taken_off_curtailable_blocks() {
+        let mut auction = AuctionSimulator::new();
+        period_order(&mut auction, 1, 0, 30.0, 8.0, false);
+        // Both blocks want 5 of the 8 offered; only the first has room to
+        // give up the 2 short
+        let mut curtailable = block(20, true, 40.0, &[(0, 5.0)]);
+        curtailable.min_acceptance_ratio = 0.25;
+        auction.add_block(curtailable).unwrap();
+        auction
+            .add_block(block(21, true, 40.0, &[(0, 5.0)]))
+            .unwrap();
+
+        let result = auction.block_clearing();
+        assert!(result.converged);
+        assert!((result.blocks[0].accepted_ratio - 0.6).abs() < 1e-9);
+        assert_eq!(result.blocks[1].status, BlockStatus::Accepted);
+        assert_eq!(result.periods[0].volume, 8.0);
+
+        // Two curtailable blocks share the shortfall by their room
+        let mut auction = AuctionSimulator::new();
+        period_order(&mut auction, 1, 0, 30.0, 8.0, false);
+        for id in [20, 21] {
+            let mut curtailable = block(id, true, 40.0, &[(0, 5.0)]);
+            curtailable.min_acceptance_ratio = 0.5;
+            auction.add_block(curtailable).unwrap();
+        }
+        let result = auction.block_clearing();
+        assert!(result.converged);
+        let ratios: Vec<f64> = result.blocks.iter().map(|b| b.accepted_ratio).collect();
+        assert!(ratios.iter().all(|r| (r - 0.8).abs() < 1e-9));
+    }
+}
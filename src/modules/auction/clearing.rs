@@ -0,0 +1,350 @@
+//! Uniform-Price Clearing
+//!
+//! Chooses the clearing price and shares the cleared volume among orders.
+//! The price is picked among the order limit prices by the usual call
+//! auction rule:
+//!
+//! 1. maximise executable volume,
+//! 2. then minimise the surplus left on one side,
+//! 3. then follow market pressure: the highest candidate when every
+//!    remaining candidate leaves excess demand, the lowest when every one
+//!    leaves excess supply,
+//! 4. otherwise the candidate range is equivalent, and the price is the
+//!    point in it nearest the reference price, or its midpoint without one.
+//!
+//! Orders fill in price priority. The first price level that cannot be
+//! filled completely (the marginal orders) is rationed pro rata, rounded
+//! down to the lot size, with leftover lots handed out one at a time in
+//! tie-break order. Only whole lots are handed out; when that leaves part of
+//! the volume unallocated, both sides trade the smaller amount and the
+//! remainder shows in the imbalance.
+
+use super::curves::{steps, CurveStep, OrderAllocation};
+use super::{sorted_sides, AuctionOrderWasm, AuctionSimulator, EPSILON};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use wasm_bindgen::prelude::*;
+
+/// Order in which rationed orders receive leftover lots
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TieBreak {
+    /// Earlier orders first
+    #[default]
+    EntryOrder = 0,
+    /// Larger orders first, then entry order
+    LargestFirst = 1,
+    /// Lower order ids first
+    LowestId = 2,
+}
+
+impl From<u8> for TieBreak {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => TieBreak::LargestFirst,
+            2 => TieBreak::LowestId,
+            _ => TieBreak::EntryOrder,
+        }
+    }
+}
+
+/// Outcome of a uniform-price clearing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClearingResult {
+    /// Clearing price (undefined when nothing trades)
+    pub price: Option<f64>,
+    pub volume: f64,
+    /// Quantity left unfilled at the price on the side with more: positive
+    /// for bids, negative for asks. Includes any remainder rationing could
+    /// not hand out in whole lots.
+    pub imbalance: f64,
+    /// Every order in curve order, bids first
+    pub allocations: Vec<OrderAllocation>,
+}
+
+#[wasm_bindgen]
+impl AuctionSimulator {
+    /// Round pro-rata fills down to multiples of `lot_size` (0 = no rounding)
+    pub fn set_lot_size(&mut self, lot_size: f64) -> Result<(), JsValue> {
+        if !lot_size.is_finite() || lot_size < 0.0 {
+            return Err(JsValue::from_str("lot_size must be a non-negative number"));
+        }
+        self.lot_size = lot_size;
+        Ok(())
+    }
+
+    /// Order for leftover lots (0=EntryOrder, 1=LargestFirst, 2=LowestId)
+    pub fn set_tie_break(&mut self, mode: u8) {
+        self.tie_break = TieBreak::from(mode);
+    }
+
+    /// Price to settle on when the rule leaves a range of equivalent prices
+    pub fn set_reference_price(&mut self, price: Option<f64>) {
+        self.reference_price = price;
+    }
+
+    /// Clear the auction and allocate fills
+    /// Returns `{ price, volume, imbalance, allocations }` with allocations as
+    /// `{ id, is_bid, price, amount, filled }`.
+    pub fn clear_auction(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.clearing())?)
+    }
+}
+
+impl AuctionSimulator {
+    pub(super) fn clearing(&self) -> ClearingResult {
+        self.clear_orders(self.single_period())
+    }
+
+    /// Clear one set of orders with this simulator's settings
+    pub(super) fn clear_orders<'a>(
+        &self,
+        orders: impl Iterator<Item = &'a AuctionOrderWasm>,
+    ) -> ClearingResult {
+        let (bids, asks) = sorted_sides(orders);
+        let price = clearing_price(&steps(&bids), &steps(&asks), self.reference_price);
+        let (demand, supply) = price.map_or((0.0, 0.0), |p| {
+            let demand: f64 = bids.iter().filter(|o| o.price >= p).map(|o| o.amount).sum();
+            let supply: f64 = asks.iter().filter(|o| o.price <= p).map(|o| o.amount).sum();
+            (demand, supply)
+        });
+
+        // Whole-lot rationing can fill a side short of the volume; trade what
+        // both sides take. Each side's fills are full levels plus whole lots,
+        // a finite set, so the strictly falling volume settles.
+        let filled =
+            |allocations: &[OrderAllocation]| -> f64 { allocations.iter().map(|a| a.filled).sum() };
+        let mut volume = demand.min(supply);
+        let (mut allocations, ask_fills) = loop {
+            let bid_fills = self.allocate(&bids, volume);
+            let ask_fills = self.allocate(&asks, volume);
+            let matched = filled(&bid_fills).min(filled(&ask_fills));
+            if matched >= volume - EPSILON {
+                break (bid_fills, ask_fills);
+            }
+            volume = matched;
+        };
+        let imbalance = if demand >= supply {
+            demand - volume
+        } else {
+            volume - supply
+        };
+        allocations.extend(ask_fills);
+        ClearingResult {
+            price,
+            volume,
+            imbalance,
+            allocations,
+        }
+    }
+
+    /// Fill `volume` from orders in curve order, rationing the first price
+    /// level that does not fit
+    fn allocate(&self, orders: &[&AuctionOrderWasm], volume: f64) -> Vec<OrderAllocation> {
+        let mut filled = vec![0.0; orders.len()];
+        let mut left = volume;
+        let mut start = 0;
+        while start < orders.len() && left > EPSILON {
+            let price = orders[start].price;
+            let end = start + orders[start..].partition_point(|o| o.price == price);
+            let level: f64 = orders[start..end].iter().map(|o| o.amount).sum();
+            if level <= left + EPSILON {
+                for i in start..end {
+                    filled[i] = orders[i].amount;
+                }
+                left -= level;
+            } else {
+                self.ration(&orders[start..end], &mut filled[start..end], left, level);
+                break;
+            }
+            start = end;
+        }
+
+        orders
+            .iter()
+            .zip(filled)
+            .map(|(order, filled)| OrderAllocation {
+                id: order.id,
+                is_bid: order.is_bid,
+                price: order.price,
+                amount: order.amount,
+                filled,
+            })
+            .collect()
+    }
+
+    /// Share `amount` of a level pro rata by size
+    fn ration(&self, orders: &[&AuctionOrderWasm], filled: &mut [f64], amount: f64, level: f64) {
+        let lot = self.lot_size;
+        for (fill, order) in filled.iter_mut().zip(orders) {
+            let share = order.amount * amount / level;
+            *fill = if lot > 0.0 {
+                (share / lot + EPSILON).floor() * lot
+            } else {
+                share
+            };
+        }
+        if lot == 0.0 {
+            return;
+        }
+
+        let mut ranked: Vec<usize> = (0..orders.len()).collect();
+        match self.tie_break {
+            TieBreak::EntryOrder => {}
+            TieBreak::LargestFirst => ranked.sort_by(|&a, &b| {
+                orders[b]
+                    .amount
+                    .partial_cmp(&orders[a].amount)
+                    .unwrap_or(Ordering::Equal)
+            }),
+            TieBreak::LowestId => ranked.sort_by_key(|&i| orders[i].id),
+        }
+        // Whatever is left short of a lot, or with no order room for one,
+        // stays unallocated
+        let mut left = amount - filled.iter().sum::<f64>();
+        while left >= lot - EPSILON {
+            let before = left;
+            for &i in &ranked {
+                if left >= lot - EPSILON && orders[i].amount - filled[i] >= lot - EPSILON {
+                    filled[i] += lot;
+                    left -= lot;
+                }
+            }
+            if left == before {
+                break;
+            }
+        }
+    }
+}
+
+/// Pick the clearing price among the step prices (see the module docs)
+fn clearing_price(
+    demand: &[CurveStep],
+    supply: &[CurveStep],
+    reference: Option<f64>,
+) -> Option<f64> {
+    // Amount bid at or above / offered at or below a price
+    let at = |steps: &[CurveStep], reaches: &dyn Fn(f64) -> bool| {
+        let n = steps.partition_point(|s| reaches(s.price));
+        n.checked_sub(1).map_or(0.0, |i| steps[i].cumulative)
+    };
+    select_price(
+        demand.iter().chain(supply).map(|s| s.price),
+        |p| (at(demand, &|bid| bid >= p), at(supply, &|ask| ask <= p)),
+        reference,
+    )
+}
+
+/// Apply the price rule to candidate prices, given `(demand, supply)` at a
+/// price
+pub(super) fn select_price(
+    candidates: impl Iterator<Item = f64>,
+    at: impl Fn(f64) -> (f64, f64),
+    reference: Option<f64>,
+) -> Option<f64> {
+    // Price-taking (infinite) limits never set the price
+    let mut candidates: Vec<f64> = candidates.filter(|p| p.is_finite()).collect();
+    candidates.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    candidates.dedup();
+    let scored: Vec<(f64, f64, f64)> = candidates
+        .into_iter()
+        .map(|p| {
+            let (d, s) = at(p);
+            (p, d.min(s), d - s)
+        })
+        .collect();
+
+    let max_volume = scored.iter().map(|c| c.1).fold(0.0, f64::max);
+    if max_volume <= EPSILON {
+        return None;
+    }
+    let best: Vec<&(f64, f64, f64)> = scored
+        .iter()
+        .filter(|c| c.1 >= max_volume - EPSILON)
+        .collect();
+    let min_surplus = best.iter().map(|c| c.2.abs()).fold(f64::INFINITY, f64::min);
+    let best: Vec<&(f64, f64, f64)> = best
+        .into_iter()
+        .filter(|c| c.2.abs() <= min_surplus + EPSILON)
+        .collect();
+
+    let (low, high) = (best[0].0, best[best.len() - 1].0);
+    if best.iter().all(|c| c.2 > EPSILON) {
+        Some(high)
+    } else if best.iter().all(|c| c.2 < -EPSILON) {
+        Some(low)
+    } else {
+        Some(reference.map_or((low + high) / 2.0, |r| r.clamp(low, high)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_rule_follows_volume_surplus_and_pressure() {
+        let mut auction = AuctionSimulator::new();
+        auction.add_order(1, 50.0, 10.0, true);
+        auction.add_order(2, 48.0, 10.0, true);
+        auction.add_order(3, 45.0, 15.0, false);
+        // 15 trades at 45 or 48, leaving excess demand either way
+        let result = auction.clearing();
+        assert_eq!((result.price, result.volume), (Some(48.0), 15.0));
+        assert_eq!(result.imbalance, 5.0);
+
+        // Balanced from 47 to 48: settle nearest the reference
+        auction.add_order(4, 47.0, 5.0, false);
+        assert_eq!(auction.calculate_clearing_price(), vec![47.5, 20.0]);
+        auction.set_reference_price(Some(40.0));
+        assert_eq!(auction.clearing().price, Some(47.0));
+
+        auction.clear();
+        auction.add_order(1, 40.0, 5.0, true);
+        auction.add_order(2, 41.0, 5.0, false);
+        assert_eq!(auction.clearing().price, None);
+        assert_eq!(auction.calculate_clearing_price(), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_marginal_orders_are_rationed_pro_rata_with_tie_break() {
+        let mut auction = AuctionSimulator::new();
+        auction.add_order(1, 60.0, 4.0, true);
+        auction.add_order(2, 50.0, 3.0, true);
+        auction.add_order(3, 50.0, 3.0, true);
+        auction.add_order(4, 50.0, 6.0, true);
+        auction.add_order(5, 40.0, 9.0, false);
+        let filled = |auction: &AuctionSimulator| -> Vec<(u32, f64)> {
+            let result = auction.clearing();
+            assert_eq!((result.price, result.volume), (Some(50.0), 9.0));
+            result
+                .allocations
+                .iter()
+                .map(|a| (a.id, a.filled))
+                .collect()
+        };
+
+        // 5 left for 12 at the margin
+        let exact = filled(&auction);
+        assert_eq!(exact[0], (1, 4.0));
+        assert!((exact[3].1 - 2.5).abs() < 1e-9);
+
+        auction.set_lot_size(1.0).unwrap();
+        assert_eq!(
+            filled(&auction),
+            vec![(1, 4.0), (2, 2.0), (3, 1.0), (4, 2.0), (5, 9.0)]
+        );
+        auction.set_tie_break(1);
+        assert_eq!(
+            filled(&auction),
+            vec![(1, 4.0), (2, 1.0), (3, 1.0), (4, 3.0), (5, 9.0)]
+        );
+
+        // 5 for the margin in lots of 2 hands out 4; the ask sells 8 to match
+        auction.set_tie_break(0);
+        auction.set_lot_size(2.0).unwrap();
+        let result = auction.clearing();
+        assert_eq!((result.volume, result.imbalance), (8.0, 8.0));
+        let filled: Vec<f64> = result.allocations.iter().map(|a| a.filled).collect();
+        assert_eq!(filled, vec![4.0, 2.0, 0.0, 2.0, 8.0]);
+    }
+}
@@ -0,0 +1,187 @@
+//! Supply and Demand Curves
+//!
+//! The aggregated step curves behind `calculate_clearing_price`, the segment
+//! where they cross and how the cleared volume is shared among orders, for
+//! plotting the market.
+
+use super::{intersect, sorted_sides, AuctionOrderWasm, AuctionSimulator};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// One price step of a curve
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurveStep {
+    pub price: f64,
+    /// Amount offered at exactly this price
+    pub amount: f64,
+    /// Amount offered at this price or better
+    pub cumulative: f64,
+}
+
+/// Where the curves cross: any price from the marginal ask to the marginal
+/// bid clears `volume`; `clearing_price` is the one the price rule picks,
+/// if any
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurveIntersection {
+    pub ask_price: f64,
+    pub bid_price: f64,
+    pub volume: f64,
+    pub clearing_price: Option<f64>,
+}
+
+/// An order's share of the cleared volume
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrderAllocation {
+    pub id: u32,
+    pub is_bid: bool,
+    pub price: f64,
+    pub amount: f64,
+    pub filled: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuctionCurves {
+    /// Bid steps, highest price first
+    pub demand: Vec<CurveStep>,
+    /// Ask steps, lowest price first
+    pub supply: Vec<CurveStep>,
+    pub intersection: Option<CurveIntersection>,
+    /// Every order in curve order, bids first
+    pub allocations: Vec<OrderAllocation>,
+}
+
+#[wasm_bindgen]
+impl AuctionSimulator {
+    /// Demand and supply step curves, their intersection and per-order fills
+    /// at the clearing price
+    /// Returns `{ demand, supply, intersection, allocations }` with steps as
+    /// `{ price, amount, cumulative }` and allocations as
+    /// `{ id, is_bid, price, amount, filled }`.
+    pub fn get_curves(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.curves())?)
+    }
+}
+
+impl AuctionSimulator {
+    pub(super) fn curves(&self) -> AuctionCurves {
+        let (bids, asks) = sorted_sides(self.single_period());
+        let demand = steps(&bids);
+        let supply = steps(&asks);
+        let clearing = self.clearing();
+        let point = |s: &CurveStep| (s.price, s.cumulative);
+        let intersection = intersect(
+            &demand.iter().map(point).collect::<Vec<_>>(),
+            &supply.iter().map(point).collect::<Vec<_>>(),
+        )
+        .map(|(bid_price, ask_price, volume)| CurveIntersection {
+            ask_price,
+            bid_price,
+            volume,
+            clearing_price: clearing.price,
+        });
+
+        AuctionCurves {
+            demand,
+            supply,
+            intersection,
+            allocations: clearing.allocations,
+        }
+    }
+}
+
+/// Merge orders at equal prices into steps
+pub(super) fn steps(orders: &[&AuctionOrderWasm]) -> Vec<CurveStep> {
+    let mut steps: Vec<CurveStep> = Vec::new();
+    let mut cumulative = 0.0;
+    for order in orders {
+        cumulative += order.amount;
+        match steps.last_mut() {
+            Some(step) if step.price == order.price => {
+                step.amount += order.amount;
+                step.cumulative = cumulative;
+            }
+            _ => steps.push(CurveStep {
+                price: order.price,
+                amount: order.amount,
+                cumulative,
+            }),
+        }
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curves_merge_price_steps_and_allocate_in_curve_order() {
+        let mut auction = AuctionSimulator::new();
+        auction.add_order(1, 50.0, 10.0, true);
+        auction.add_order(2, 50.0, 5.0, true);
+        auction.add_order(3, 40.0, 10.0, true);
+        auction.add_order(4, 30.0, 8.0, false);
+        auction.add_order(5, 45.0, 12.0, false);
+
+        let curves = auction.curves();
+        assert_eq!(
+            curves.demand,
+            vec![
+                CurveStep {
+                    price: 50.0,
+                    amount: 15.0,
+                    cumulative: 15.0,
+                },
+                CurveStep {
+                    price: 40.0,
+                    amount: 10.0,
+                    cumulative: 25.0,
+                },
+            ]
+        );
+        assert_eq!(curves.supply[1].cumulative, 20.0);
+        assert_eq!(
+            curves.intersection,
+            Some(CurveIntersection {
+                ask_price: 45.0,
+                bid_price: 50.0,
+                volume: 15.0,
+                clearing_price: Some(45.0),
+            })
+        );
+        // Excess supply at 45 and 50 pushes the price down
+        assert_eq!(auction.calculate_clearing_price(), vec![45.0, 15.0]);
+
+        let filled: Vec<(u32, f64)> = curves
+            .allocations
+            .iter()
+            .map(|a| (a.id, a.filled))
+            .collect();
+        assert_eq!(
+            filled,
+            vec![(1, 10.0), (2, 5.0), (3, 0.0), (4, 8.0), (5, 7.0)]
+        );
+    }
+
+    #[test]
+    fn test_intersection_finds_volume_below_the_top_bid() {
+        let mut auction = AuctionSimulator::new();
+        auction.add_order(1, 50.0, 10.0, true);
+        auction.add_order(2, 40.0, 15.0, true);
+        auction.add_order(3, 30.0, 20.0, false);
+        auction.add_order(4, 45.0, 1.0, false);
+
+        // Only the 30 ask reaches the 40 bid, but 25 demand there clears 20
+        let intersection = auction.curves().intersection.unwrap();
+        assert_eq!(
+            (
+                intersection.bid_price,
+                intersection.ask_price,
+                intersection.volume,
+                intersection.clearing_price
+            ),
+            (40.0, 30.0, 20.0, Some(40.0))
+        );
+        assert_eq!(auction.calculate_clearing_price(), vec![40.0, 20.0]);
+    }
+}
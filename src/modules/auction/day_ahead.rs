@@ -0,0 +1,509 @@
+//! Day-Ahead Clearing
+//!
+//! Clears a whole delivery day of hourly or quarter-hourly periods in one
+//! call, each period with its own price and volume. Besides single-price
+//! orders (`place_order` with a `period`) and block orders, a curve order
+//! carries a price/amount curve per period:
+//!
+//! - `Step`: a bid buys the amount of the next point at or above the price
+//!   and nothing above its last point; an ask sells the amount of the last
+//!   point at or below the price and nothing below its first point.
+//! - `Linear`: amounts are interpolated between points; beyond the ends a
+//!   bid keeps its first amount below the curve and buys nothing above it,
+//!   and an ask sells nothing below the curve and its last amount above it.
+//!
+//! Each period is cleared with the same price rule as `clear_auction`. The
+//! candidate prices are the curve points plus, between them, the prices
+//! where linear demand and supply meet. Orders at the clearing price that
+//! cannot all fill are rationed pro rata; fills are not rounded to lots.
+
+use super::clearing::select_price;
+use super::{AuctionOrderWasm, AuctionSimulator, BlockResult, EPSILON};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use wasm_bindgen::prelude::*;
+
+/// Most periods in a delivery day (100 quarter hours on a clock change)
+pub const MAX_PERIODS: u32 = 100;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+    #[default]
+    Step,
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurvePoint {
+    pub price: f64,
+    pub amount: f64,
+}
+
+/// Points in ascending price order: amounts fall for bids and rise for asks
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeriodCurve {
+    pub period: u32,
+    pub points: Vec<CurvePoint>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurveOrder {
+    pub id: u32,
+    pub is_bid: bool,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    pub curves: Vec<PeriodCurve>,
+}
+
+/// Amount an order (or curve order) trades in a period
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrderFill {
+    pub id: u32,
+    pub is_bid: bool,
+    pub filled: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DayAheadPeriod {
+    pub period: u32,
+    /// Clearing price (undefined when nothing trades)
+    pub price: Option<f64>,
+    pub volume: f64,
+    /// Demand minus supply at the price, blocks included
+    pub imbalance: f64,
+    /// Orders and curve orders for the period; blocks trade their accepted
+    /// ratio of the profile
+    pub fills: Vec<OrderFill>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DayAheadClearing {
+    /// One entry per period of the day
+    pub periods: Vec<DayAheadPeriod>,
+    pub blocks: Vec<BlockResult>,
+    pub paradoxically_rejected: Vec<u32>,
+    /// False if block selection stopped at its round limit
+    pub converged: bool,
+}
+
+#[wasm_bindgen]
+impl AuctionSimulator {
+    /// Add a curve order
+    /// Expects `{ id, is_bid, interpolation?, curves: [{ period, points:
+    /// [{ price, amount }] }] }` with `interpolation` as "Step" (default) or
+    /// "Linear".
+    pub fn add_curve_order(&mut self, order: JsValue) -> Result<(), JsValue> {
+        let order: CurveOrder = serde_wasm_bindgen::from_value(order)?;
+        self.add_curve(order).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Clear periods `0..periods` (24 hourly or 96 quarter-hourly periods on
+    /// a normal day), with block orders
+    /// Returns `{ periods: [{ period, price, volume, imbalance, fills: [{ id,
+    /// is_bid, filled }] }], blocks, paradoxically_rejected, converged }`.
+    pub fn clear_day(&self, periods: u32) -> Result<JsValue, JsValue> {
+        let result = self.day_ahead(periods).map_err(|e| JsValue::from_str(&e))?;
+        Ok(serde_wasm_bindgen::to_value(&result)?)
+    }
+}
+
+impl AuctionSimulator {
+    pub(super) fn add_curve(&mut self, order: CurveOrder) -> Result<(), String> {
+        if self.curve_orders.iter().any(|o| o.id == order.id) {
+            return Err(format!("Curve order {} already exists", order.id));
+        }
+        if order.curves.is_empty() {
+            return Err("Curve order needs at least one period curve".to_string());
+        }
+        for (i, curve) in order.curves.iter().enumerate() {
+            if order.curves[..i].iter().any(|c| c.period == curve.period) {
+                return Err(format!("Period {} has more than one curve", curve.period));
+            }
+            let points = &curve.points;
+            if points.is_empty()
+                || points
+                    .iter()
+                    .any(|p| !p.price.is_finite() || !p.amount.is_finite() || p.amount < 0.0)
+            {
+                return Err(format!(
+                    "Curve for period {} needs finite prices and non-negative amounts",
+                    curve.period
+                ));
+            }
+            let monotone = points.windows(2).all(|w| {
+                w[0].price < w[1].price
+                    && if order.is_bid {
+                        w[0].amount >= w[1].amount
+                    } else {
+                        w[0].amount <= w[1].amount
+                    }
+            });
+            if !monotone {
+                return Err(format!(
+                    "Curve for period {} must rise in price with {} amounts",
+                    curve.period,
+                    if order.is_bid { "falling" } else { "rising" }
+                ));
+            }
+        }
+        self.curve_orders.push(order);
+        Ok(())
+    }
+
+    pub(super) fn day_ahead(&self, periods: u32) -> Result<DayAheadClearing, String> {
+        if periods == 0 || periods > MAX_PERIODS {
+            return Err(format!("periods must be between 1 and {}", MAX_PERIODS));
+        }
+        let outside = self
+            .orders
+            .iter()
+            .map(|o| (o.id, o.period))
+            .chain(
+                self.curve_orders
+                    .iter()
+                    .flat_map(|o| o.curves.iter().map(move |c| (o.id, c.period))),
+            )
+            .chain(
+                self.blocks
+                    .iter()
+                    .flat_map(|b| b.profile.iter().map(move |p| (b.id, p.period))),
+            )
+            .find(|&(_, period)| period >= periods);
+        if let Some((id, period)) = outside {
+            return Err(format!(
+                "Order {} is for period {}, outside the day",
+                id, period
+            ));
+        }
+
+        let selection = self.select_blocks(&(0..periods).collect::<Vec<_>>());
+        Ok(DayAheadClearing {
+            periods: selection.periods,
+            blocks: selection.blocks,
+            paradoxically_rejected: selection.paradoxically_rejected,
+            converged: selection.converged,
+        })
+    }
+
+    /// Clear one period's orders and curve orders, plus `extra` orders
+    /// (blocks entered as price-taking orders) that are left out of `fills`
+    pub(super) fn clear_period(&self, period: u32, extra: &[AuctionOrderWasm]) -> DayAheadPeriod {
+        let single = |o: &AuctionOrderWasm, report: bool| Schedule {
+            id: o.id,
+            is_bid: o.is_bid,
+            interpolation: Interpolation::Step,
+            points: vec![CurvePoint {
+                price: o.price,
+                amount: o.amount,
+            }],
+            report,
+        };
+        let schedules: Vec<Schedule> = self
+            .orders
+            .iter()
+            .filter(|o| o.period == period)
+            .map(|o| single(o, true))
+            .chain(self.curve_orders.iter().flat_map(|o| {
+                o.curves
+                    .iter()
+                    .filter(|c| c.period == period)
+                    .map(|c| Schedule {
+                        id: o.id,
+                        is_bid: o.is_bid,
+                        interpolation: o.interpolation,
+                        points: c.points.clone(),
+                        report: true,
+                    })
+            }))
+            .chain(extra.iter().map(|o| single(o, false)))
+            .collect();
+
+        let totals = |p: f64, strict: bool| {
+            schedules.iter().fold((0.0, 0.0), |(d, s), schedule| {
+                let amount = schedule.amount(p, strict);
+                if schedule.is_bid {
+                    (d + amount, s)
+                } else {
+                    (d, s + amount)
+                }
+            })
+        };
+
+        let mut breakpoints: Vec<f64> = schedules
+            .iter()
+            .flat_map(|s| s.points.iter().map(|p| p.price))
+            .filter(|p| p.is_finite())
+            .collect();
+        breakpoints.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        breakpoints.dedup();
+        // Between breakpoints demand and supply are linear, so two samples
+        // locate where they meet
+        let mut candidates = breakpoints.clone();
+        if schedules
+            .iter()
+            .any(|s| s.interpolation == Interpolation::Linear)
+        {
+            for w in breakpoints.windows(2) {
+                let (x1, x2) = (w[0] + (w[1] - w[0]) / 3.0, w[0] + (w[1] - w[0]) * 2.0 / 3.0);
+                let excess = |x: f64| {
+                    let (d, s) = totals(x, false);
+                    d - s
+                };
+                let (e1, e2) = (excess(x1), excess(x2));
+                if (e1 - e2).abs() > EPSILON {
+                    let root = x1 + e1 * (x2 - x1) / (e1 - e2);
+                    if root > w[0] && root < w[1] {
+                        candidates.push(root);
+                    }
+                }
+            }
+        }
+
+        let price = select_price(
+            candidates.into_iter(),
+            |p| totals(p, false),
+            self.reference_price,
+        );
+        let Some(p) = price else {
+            return DayAheadPeriod {
+                period,
+                price: None,
+                volume: 0.0,
+                imbalance: 0.0,
+                fills: schedules
+                    .iter()
+                    .filter(|s| s.report)
+                    .map(|s| s.fill(0.0))
+                    .collect(),
+            };
+        };
+        let (demand, supply) = totals(p, false);
+        let volume = demand.min(supply);
+
+        // Orders beyond the price fill; those exactly at it share the rest
+        let (firm_demand, firm_supply) = totals(p, true);
+        let ration = |firm: f64, full: f64| {
+            if firm >= volume - EPSILON {
+                (volume / firm.max(EPSILON), 0.0)
+            } else {
+                (1.0, (volume - firm) / (full - firm))
+            }
+        };
+        let (bid_firm, bid_marginal) = ration(firm_demand, demand);
+        let (ask_firm, ask_marginal) = ration(firm_supply, supply);
+        let fills = schedules
+            .iter()
+            .filter(|s| s.report)
+            .map(|s| {
+                let (firm_share, marginal_share) = if s.is_bid {
+                    (bid_firm, bid_marginal)
+                } else {
+                    (ask_firm, ask_marginal)
+                };
+                let firm = s.amount(p, true);
+                let marginal = s.amount(p, false) - firm;
+                s.fill(firm * firm_share.min(1.0) + marginal * marginal_share.min(1.0))
+            })
+            .collect();
+
+        DayAheadPeriod {
+            period,
+            price,
+            volume,
+            imbalance: demand - supply,
+            fills,
+        }
+    }
+}
+
+/// One order's curve in the period being cleared
+struct Schedule {
+    id: u32,
+    is_bid: bool,
+    interpolation: Interpolation,
+    points: Vec<CurvePoint>,
+    report: bool,
+}
+
+impl Schedule {
+    /// Amount wanted at `price`; `strict` gives the amount just beyond it
+    /// (above for bids, below for asks), leaving out what trades only at
+    /// exactly that price
+    fn amount(&self, price: f64, strict: bool) -> f64 {
+        let points = &self.points;
+        let (first, last) = (points[0], points[points.len() - 1]);
+        // Out of range, or only reached at exactly this price
+        let beyond = if self.is_bid {
+            price > last.price || (strict && price == last.price)
+        } else {
+            price < first.price || (strict && price == first.price)
+        };
+        if beyond {
+            return 0.0;
+        }
+
+        match self.interpolation {
+            Interpolation::Step if self.is_bid => points
+                .iter()
+                .find(|p| {
+                    if strict {
+                        p.price > price
+                    } else {
+                        p.price >= price
+                    }
+                })
+                .map_or(0.0, |p| p.amount),
+            Interpolation::Step => points
+                .iter()
+                .rev()
+                .find(|p| {
+                    if strict {
+                        p.price < price
+                    } else {
+                        p.price <= price
+                    }
+                })
+                .map_or(0.0, |p| p.amount),
+            Interpolation::Linear if price <= first.price => first.amount,
+            Interpolation::Linear if price >= last.price => last.amount,
+            Interpolation::Linear => {
+                let i = points.partition_point(|p| p.price <= price);
+                let (a, b) = (points[i - 1], points[i]);
+                a.amount + (b.amount - a.amount) * (price - a.price) / (b.price - a.price)
+            }
+        }
+    }
+
+    fn fill(&self, filled: f64) -> OrderFill {
+        OrderFill {
+            id: self.id,
+            is_bid: self.is_bid,
+            filled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(
+        id: u32,
+        is_bid: bool,
+        interpolation: Interpolation,
+        period: u32,
+        points: &[(f64, f64)],
+    ) -> CurveOrder {
+        CurveOrder {
+            id,
+            is_bid,
+            interpolation,
+            curves: vec![PeriodCurve {
+                period,
+                points: points
+                    .iter()
+                    .map(|&(price, amount)| CurvePoint { price, amount })
+                    .collect(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_linear_curves_meet_between_points() {
+        let mut auction = AuctionSimulator::new();
+        // Demand falls from 100 at 0 to 0 at 100; supply rises from 0 at 0 to 50 at 100
+        auction
+            .add_curve(curve(
+                1,
+                true,
+                Interpolation::Linear,
+                0,
+                &[(0.0, 100.0), (100.0, 0.0)],
+            ))
+            .unwrap();
+        auction
+            .add_curve(curve(
+                2,
+                false,
+                Interpolation::Linear,
+                0,
+                &[(0.0, 0.0), (100.0, 50.0)],
+            ))
+            .unwrap();
+        // Period 1 only has single-price orders
+        auction.orders.push(AuctionOrderWasm {
+            id: 3,
+            price: 40.0,
+            amount: 5.0,
+            is_bid: true,
+            period: 1,
+            zone: 0,
+            participant: None,
+        });
+        auction.orders.push(AuctionOrderWasm {
+            id: 4,
+            price: 30.0,
+            amount: 8.0,
+            is_bid: false,
+            period: 1,
+            zone: 0,
+            participant: None,
+        });
+
+        let result = auction.day_ahead(24).unwrap();
+        assert_eq!(result.periods.len(), 24);
+        // 100 - p = p / 2 at p = 200 / 3
+        let first = &result.periods[0];
+        let price = first.price.unwrap();
+        assert!((price - 200.0 / 3.0).abs() < 1e-6);
+        assert!((first.volume - 100.0 / 3.0).abs() < 1e-6);
+        assert!(first
+            .fills
+            .iter()
+            .all(|f| (f.filled - first.volume).abs() < 1e-6));
+
+        let second = &result.periods[1];
+        assert_eq!((second.price, second.volume), (Some(30.0), 5.0));
+        assert_eq!(second.fills[1].filled, 5.0);
+        assert_eq!(result.periods[2].price, None);
+
+        assert!(auction.day_ahead(1).is_err());
+        assert!(auction.day_ahead(MAX_PERIODS + 1).is_err());
+    }
+
+    #[test]
+    fn test_step_curve_matches_single_price_orders() {
+        let mut auction = AuctionSimulator::new();
+        // Buys 10 up to 40 and 4 up to 60, as two single-price bids would
+        auction
+            .add_curve(curve(
+                1,
+                true,
+                Interpolation::Step,
+                0,
+                &[(40.0, 10.0), (60.0, 4.0)],
+            ))
+            .unwrap();
+        auction.add_order(2, 35.0, 12.0, false);
+        let day = auction.day_ahead(1).unwrap();
+
+        let mut single = AuctionSimulator::new();
+        single.add_order(1, 60.0, 4.0, true);
+        single.add_order(1, 40.0, 6.0, true);
+        single.add_order(2, 35.0, 12.0, false);
+        let clearing = single.clearing();
+        assert_eq!(day.periods[0].price, clearing.price);
+        assert_eq!(day.periods[0].volume, clearing.volume);
+        assert_eq!(day.periods[0].fills[0].filled, 10.0);
+
+        assert!(auction
+            .add_curve(curve(
+                3,
+                true,
+                Interpolation::Step,
+                0,
+                &[(40.0, 1.0), (60.0, 4.0)]
+            ))
+            .is_err());
+    }
+}
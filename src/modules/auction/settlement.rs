@@ -0,0 +1,288 @@
+//! Settlement Rules
+//!
+//! Settles the period-0 clearing of `clear_auction` under a selectable
+//! pricing rule, so the same order set can be compared across rules:
+//!
+//! - `Uniform`: every fill trades at the clearing price.
+//! - `PayAsBid`: every fill trades at its own limit price.
+//! - `Vickrey`: VCG payments. Each participant pays the welfare the others
+//!   lose by its presence: the others' welfare when cleared without it,
+//!   minus their welfare in the actual clearing.
+//!
+//! Payments are positive when a participant pays and negative when it is
+//! paid. Surplus is a participant's value of its fills (bid limits bought
+//! minus ask limits sold) less its payment, and the budget balance is what
+//! the auctioneer keeps.
+
+use super::{sorted_sides, AuctionOrderWasm, AuctionSimulator};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// How cleared orders pay
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum PricingRule {
+    #[default]
+    Uniform = 0,
+    PayAsBid = 1,
+    /// Vickrey-Clarke-Groves second pricing
+    Vickrey = 2,
+}
+
+impl From<u8> for PricingRule {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => PricingRule::PayAsBid,
+            2 => PricingRule::Vickrey,
+            _ => PricingRule::Uniform,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParticipantSettlement {
+    pub participant: u32,
+    pub bought: f64,
+    pub sold: f64,
+    /// Positive when the participant pays
+    pub payment: f64,
+    pub surplus: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settlement {
+    pub rule: PricingRule,
+    /// Uniform clearing price the fills are based on
+    pub price: Option<f64>,
+    pub volume: f64,
+    /// Participants in id order
+    pub participants: Vec<ParticipantSettlement>,
+    /// Surplus of participants that bought more than they sold
+    pub buyer_surplus: f64,
+    /// Surplus of the other participants
+    pub seller_surplus: f64,
+    /// Payments received less payments made by the auctioneer
+    pub budget_balance: f64,
+    /// Participant surplus plus budget balance: the welfare of the clearing
+    pub total_surplus: f64,
+}
+
+#[wasm_bindgen]
+impl AuctionSimulator {
+    /// Pricing rule for `settle` (0=Uniform, 1=PayAsBid, 2=Vickrey)
+    pub fn set_pricing_rule(&mut self, rule: u8) {
+        self.pricing_rule = PricingRule::from(rule);
+    }
+
+    /// Clear the auction and settle it under the pricing rule
+    /// Returns `{ rule, price, volume, participants: [{ participant, bought,
+    /// sold, payment, surplus }], buyer_surplus, seller_surplus,
+    /// budget_balance, total_surplus }`.
+    pub fn settle(&self) -> Result<JsValue, JsValue> {
+        let result = self.settlement().map_err(|e| JsValue::from_str(&e))?;
+        Ok(serde_wasm_bindgen::to_value(&result)?)
+    }
+}
+
+impl AuctionSimulator {
+    pub(super) fn settlement(&self) -> Result<Settlement, String> {
+        let orders: Vec<&AuctionOrderWasm> = self.single_period().collect();
+        if let Some(order) = orders.iter().find(|o| !o.price.is_finite()) {
+            return Err(format!(
+                "Order {} needs a finite price for settlement",
+                order.id
+            ));
+        }
+        let (price, volume, fills) = self.fills(&orders);
+        let welfare: f64 = fills.iter().map(|&(o, filled)| value(o, filled)).sum();
+
+        let mut participants: BTreeMap<u32, ParticipantSettlement> = orders
+            .iter()
+            .map(|o| {
+                (
+                    o.participant(),
+                    ParticipantSettlement {
+                        participant: o.participant(),
+                        bought: 0.0,
+                        sold: 0.0,
+                        payment: 0.0,
+                        surplus: 0.0,
+                    },
+                )
+            })
+            .collect();
+        for &(order, filled) in &fills {
+            let p = participants
+                .get_mut(&order.participant())
+                .expect("every order has a participant entry");
+            if order.is_bid {
+                p.bought += filled;
+            } else {
+                p.sold += filled;
+            }
+            // Own value counts as surplus until the payment is taken off
+            p.surplus += value(order, filled);
+            p.payment += match self.pricing_rule {
+                PricingRule::Uniform => {
+                    let traded = price.unwrap_or(0.0) * filled;
+                    if order.is_bid {
+                        traded
+                    } else {
+                        -traded
+                    }
+                }
+                PricingRule::PayAsBid => value(order, filled),
+                PricingRule::Vickrey => 0.0,
+            };
+        }
+        if self.pricing_rule == PricingRule::Vickrey {
+            for p in participants.values_mut() {
+                let others: Vec<&AuctionOrderWasm> = orders
+                    .iter()
+                    .copied()
+                    .filter(|o| o.participant() != p.participant)
+                    .collect();
+                let (_, _, without) = self.fills(&others);
+                let welfare_without: f64 = without.iter().map(|&(o, f)| value(o, f)).sum();
+                p.payment = welfare_without - (welfare - p.surplus);
+            }
+        }
+
+        let mut settlement = Settlement {
+            rule: self.pricing_rule,
+            price,
+            volume,
+            participants: Vec::new(),
+            buyer_surplus: 0.0,
+            seller_surplus: 0.0,
+            budget_balance: 0.0,
+            total_surplus: welfare,
+        };
+        for mut p in participants.into_values() {
+            p.surplus -= p.payment;
+            if p.bought > p.sold {
+                settlement.buyer_surplus += p.surplus;
+            } else {
+                settlement.seller_surplus += p.surplus;
+            }
+            settlement.budget_balance += p.payment;
+            settlement.participants.push(p);
+        }
+        Ok(settlement)
+    }
+
+    /// Clear `orders` and pair each with its fill
+    fn fills<'a>(
+        &self,
+        orders: &[&'a AuctionOrderWasm],
+    ) -> (Option<f64>, f64, Vec<(&'a AuctionOrderWasm, f64)>) {
+        let result = self.clear_orders(orders.iter().copied());
+        // Allocations follow the same curve order
+        let (bids, asks) = sorted_sides(orders.iter().copied());
+        let fills = bids
+            .into_iter()
+            .chain(asks)
+            .zip(&result.allocations)
+            .map(|(order, allocation)| (order, allocation.filled))
+            .collect();
+        (result.price, result.volume, fills)
+    }
+}
+
+/// Value of a fill at the order's limit: paid for bids, negative cost for asks
+fn value(order: &AuctionOrderWasm, filled: f64) -> f64 {
+    if order.is_bid {
+        order.price * filled
+    } else {
+        -order.price * filled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Payment and surplus per participant, then buyer and seller surplus,
+    // budget balance and total surplus
+    fn summary(auction: &AuctionSimulator) -> (Vec<(u32, f64, f64)>, [f64; 4]) {
+        let s = auction.settlement().unwrap();
+        (
+            s.participants
+                .iter()
+                .map(|p| (p.participant, p.payment, p.surplus))
+                .collect(),
+            [
+                s.buyer_surplus,
+                s.seller_surplus,
+                s.budget_balance,
+                s.total_surplus,
+            ],
+        )
+    }
+
+    #[test]
+    fn test_rules_settle_the_same_clearing() {
+        let mut auction = AuctionSimulator::new();
+        auction.add_order(1, 50.0, 10.0, true);
+        auction.add_order(2, 40.0, 10.0, true);
+        auction.add_order(3, 20.0, 10.0, false);
+        auction.add_order(4, 30.0, 10.0, false);
+
+        // Balanced from 30 to 40: 35
+        assert_eq!(
+            summary(&auction),
+            (
+                vec![
+                    (1, 350.0, 150.0),
+                    (2, 350.0, 50.0),
+                    (3, -350.0, 150.0),
+                    (4, -350.0, 50.0),
+                ],
+                [200.0, 200.0, 0.0, 400.0],
+            )
+        );
+
+        auction.set_pricing_rule(1);
+        let (participants, totals) = summary(&auction);
+        assert!(participants.iter().all(|p| p.2 == 0.0));
+        assert_eq!(totals, [0.0, 0.0, 400.0, 400.0]);
+
+        // Each pays the welfare the others lose: the auctioneer runs a deficit
+        auction.set_pricing_rule(2);
+        assert_eq!(
+            summary(&auction),
+            (
+                vec![
+                    (1, 300.0, 200.0),
+                    (2, 300.0, 100.0),
+                    (3, -400.0, 200.0),
+                    (4, -400.0, 100.0),
+                ],
+                [300.0, 300.0, -200.0, 400.0],
+            )
+        );
+    }
+
+    #[test]
+    fn test_vickrey_groups_orders_by_participant() {
+        let mut auction = AuctionSimulator::new();
+        auction.add_order(1, 50.0, 10.0, true);
+        auction.add_order(2, 40.0, 10.0, true);
+        auction.add_order(3, 20.0, 10.0, false);
+        auction.add_order(4, 30.0, 10.0, false);
+        // One seller behind both asks
+        for order in auction.orders.iter_mut().filter(|o| !o.is_bid) {
+            order.participant = Some(9);
+        }
+        auction.set_pricing_rule(2);
+
+        let settlement = auction.settlement().unwrap();
+        assert_eq!(settlement.participants.len(), 3);
+        // Without the seller nothing trades, so it is paid the buyers' value
+        let seller = settlement.participants[2];
+        assert_eq!((seller.participant, seller.sold), (9, 20.0));
+        assert_eq!(seller.payment, -900.0);
+        assert_eq!(seller.surplus, 400.0);
+        assert_eq!(settlement.budget_balance, -300.0);
+    }
+}
@@ -0,0 +1,468 @@
+//! Zonal Market Coupling
+//!
+//! Clears period 0 over several grid zones linked by interconnectors with
+//! limited transfer capacity; other periods, blocks and curve orders are
+//! not coupled. Orders are tagged with a `zone`; each
+//! interconnector carries power one way, so a two-way link is two
+//! interconnectors.
+//!
+//! Flows maximise total welfare: every ask feeds its zone from a source at
+//! its price, every bid draws from its zone into a sink at minus its price,
+//! and successive shortest paths (Bellman-Ford over the residual network)
+//! push power while a path still gains. Zones joined by interconnectors
+//! that carry power below capacity form one price area. Each area is then
+//! cleared like a single market, with flows on congested interconnectors
+//! entering as price-taking exports and imports, so prices follow the same
+//! rule as `clear_auction`. The congestion rent of an interconnector is its
+//! flow times the price difference from its sending to its receiving zone.
+
+use super::{AuctionOrderWasm, AuctionSimulator, EPSILON};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use wasm_bindgen::prelude::*;
+
+/// One-way transfer capacity between zones
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Interconnector {
+    pub from: u32,
+    pub to: u32,
+    pub capacity: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZoneClearing {
+    pub zone: u32,
+    /// Price of the zone's price area (undefined when nothing trades there)
+    pub price: Option<f64>,
+    /// Cleared bids in the zone
+    pub demand: f64,
+    /// Cleared asks in the zone
+    pub supply: f64,
+    /// Supply minus demand, sent out over interconnectors
+    pub net_export: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InterconnectorFlow {
+    pub from: u32,
+    pub to: u32,
+    pub capacity: f64,
+    pub flow: f64,
+    pub congested: bool,
+    /// Flow times the receiving minus the sending zone price
+    pub congestion_rent: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZonalClearing {
+    /// Zones with orders or interconnectors, in id order
+    pub zones: Vec<ZoneClearing>,
+    /// Interconnectors in the order they were set
+    pub flows: Vec<InterconnectorFlow>,
+    pub congestion_rent: f64,
+}
+
+#[wasm_bindgen]
+impl AuctionSimulator {
+    /// Set the transfer capacity from one zone to another, replacing any
+    /// earlier capacity for that direction
+    pub fn set_interconnector(&mut self, from: u32, to: u32, capacity: f64) -> Result<(), JsValue> {
+        self.add_interconnector(from, to, capacity)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Clear period 0 across zones. Orders for other periods, block orders
+    /// and curve orders are left out.
+    /// Returns `{ zones: [{ zone, price, demand, supply, net_export }], flows:
+    /// [{ from, to, capacity, flow, congested, congestion_rent }],
+    /// congestion_rent }`.
+    pub fn clear_zones(&self) -> Result<JsValue, JsValue> {
+        let result = self.zonal_clearing().map_err(|e| JsValue::from_str(&e))?;
+        Ok(serde_wasm_bindgen::to_value(&result)?)
+    }
+}
+
+impl AuctionSimulator {
+    pub(super) fn add_interconnector(
+        &mut self,
+        from: u32,
+        to: u32,
+        capacity: f64,
+    ) -> Result<(), String> {
+        if from == to {
+            return Err("An interconnector must join two different zones".to_string());
+        }
+        if !capacity.is_finite() || capacity < 0.0 {
+            return Err("capacity must be a non-negative number".to_string());
+        }
+        match self
+            .interconnectors
+            .iter_mut()
+            .find(|i| i.from == from && i.to == to)
+        {
+            Some(line) => line.capacity = capacity,
+            None => self
+                .interconnectors
+                .push(Interconnector { from, to, capacity }),
+        }
+        Ok(())
+    }
+
+    pub(super) fn zonal_clearing(&self) -> Result<ZonalClearing, String> {
+        let orders: Vec<&AuctionOrderWasm> = self.single_period().collect();
+        if let Some(order) = orders.iter().find(|o| !o.price.is_finite()) {
+            return Err(format!(
+                "Order {} needs a finite price for zonal clearing",
+                order.id
+            ));
+        }
+        let zones: Vec<u32> = orders
+            .iter()
+            .map(|o| o.zone)
+            .chain(self.interconnectors.iter().flat_map(|i| [i.from, i.to]))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let node = |zone: u32| {
+            2 + zones
+                .binary_search(&zone)
+                .expect("every order and interconnector zone is collected above")
+        };
+
+        // Source 0 and sink 1, then one node per zone
+        let mut network = Network::new(2 + zones.len());
+        let order_edges: Vec<usize> = orders
+            .iter()
+            .map(|o| {
+                if o.is_bid {
+                    network.add(node(o.zone), 1, o.amount, -o.price)
+                } else {
+                    network.add(0, node(o.zone), o.amount, o.price)
+                }
+            })
+            .collect();
+        let line_edges: Vec<usize> = self
+            .interconnectors
+            .iter()
+            .map(|i| network.add(node(i.from), node(i.to), i.capacity, 0.0))
+            .collect();
+        network.maximise_welfare(0, 1);
+
+        // Power sent both ways over a pair of lines only nets out
+        let gross: Vec<f64> = line_edges.iter().map(|&e| network.flow(e)).collect();
+        let flows: Vec<f64> = self
+            .interconnectors
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let back = self
+                    .interconnectors
+                    .iter()
+                    .position(|b| b.from == line.to && b.to == line.from)
+                    .map_or(0.0, |b| gross[b]);
+                (gross[i] - back).max(0.0)
+            })
+            .collect();
+        let congested: Vec<bool> = self
+            .interconnectors
+            .iter()
+            .zip(&flows)
+            .map(|(line, &flow)| flow >= line.capacity - EPSILON)
+            .collect();
+
+        // Price areas: zones joined by lines carrying power below capacity
+        let mut area: Vec<usize> = (0..zones.len()).collect();
+        fn root(area: &mut [usize], mut z: usize) -> usize {
+            while area[z] != z {
+                area[z] = area[area[z]];
+                z = area[z];
+            }
+            z
+        }
+        for (i, line) in self.interconnectors.iter().enumerate() {
+            if flows[i] > EPSILON && !congested[i] {
+                let (a, b) = (
+                    root(&mut area, node(line.from) - 2),
+                    root(&mut area, node(line.to) - 2),
+                );
+                area[a] = b;
+            }
+        }
+        let areas: Vec<usize> = (0..zones.len()).map(|z| root(&mut area, z)).collect();
+
+        let mut prices: Vec<Option<f64>> = vec![None; zones.len()];
+        for a in areas.iter().copied().collect::<BTreeSet<_>>() {
+            let in_area = |zone: u32| areas[node(zone) - 2] == a;
+            // Congested flows leave as price-taking bids and arrive as
+            // price-taking asks
+            let boundary: Vec<AuctionOrderWasm> = self
+                .interconnectors
+                .iter()
+                .zip(&flows)
+                .filter(|(line, &flow)| flow > EPSILON && in_area(line.from) != in_area(line.to))
+                .map(|(line, &flow)| {
+                    let export = in_area(line.from);
+                    AuctionOrderWasm {
+                        id: 0,
+                        price: if export {
+                            f64::INFINITY
+                        } else {
+                            f64::NEG_INFINITY
+                        },
+                        amount: flow,
+                        is_bid: export,
+                        period: 0,
+                        zone: if export { line.from } else { line.to },
+                        participant: None,
+                    }
+                })
+                .collect();
+            let price = self
+                .clear_orders(
+                    orders
+                        .iter()
+                        .copied()
+                        .filter(|o| in_area(o.zone))
+                        .chain(boundary.iter()),
+                )
+                .price;
+            for (z, _) in areas.iter().enumerate().filter(|(_, &r)| r == a) {
+                prices[z] = price;
+            }
+        }
+
+        let zone_results = zones
+            .iter()
+            .enumerate()
+            .map(|(z, &zone)| {
+                let cleared = |is_bid: bool| -> f64 {
+                    orders
+                        .iter()
+                        .zip(&order_edges)
+                        .filter(|(o, _)| o.zone == zone && o.is_bid == is_bid)
+                        .map(|(_, &e)| network.flow(e))
+                        .sum()
+                };
+                let (demand, supply) = (cleared(true), cleared(false));
+                ZoneClearing {
+                    zone,
+                    price: prices[z],
+                    demand,
+                    supply,
+                    net_export: supply - demand,
+                }
+            })
+            .collect();
+        let line_results: Vec<InterconnectorFlow> = self
+            .interconnectors
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let spread = prices[node(line.to) - 2]
+                    .zip(prices[node(line.from) - 2])
+                    .map_or(0.0, |(to, from)| to - from);
+                InterconnectorFlow {
+                    from: line.from,
+                    to: line.to,
+                    capacity: line.capacity,
+                    flow: flows[i],
+                    congested: congested[i],
+                    congestion_rent: flows[i] * spread,
+                }
+            })
+            .collect();
+
+        Ok(ZonalClearing {
+            zones: zone_results,
+            congestion_rent: line_results.iter().map(|l| l.congestion_rent).sum(),
+            flows: line_results,
+        })
+    }
+}
+
+/// Residual network for min-cost flow; edge `e ^ 1` is the reverse of `e`
+struct Network {
+    to: Vec<usize>,
+    capacity: Vec<f64>,
+    cost: Vec<f64>,
+    adjacent: Vec<Vec<usize>>,
+}
+
+impl Network {
+    fn new(nodes: usize) -> Self {
+        Self {
+            to: Vec::new(),
+            capacity: Vec::new(),
+            cost: Vec::new(),
+            adjacent: vec![Vec::new(); nodes],
+        }
+    }
+
+    fn add(&mut self, from: usize, to: usize, capacity: f64, cost: f64) -> usize {
+        let e = self.to.len();
+        for (a, b, cap, c) in [(from, to, capacity, cost), (to, from, 0.0, -cost)] {
+            self.adjacent[a].push(self.to.len());
+            self.to.push(b);
+            self.capacity.push(cap);
+            self.cost.push(c);
+        }
+        e
+    }
+
+    fn flow(&self, e: usize) -> f64 {
+        self.capacity[e ^ 1]
+    }
+
+    /// Push flow along the cheapest path while its cost is negative
+    fn maximise_welfare(&mut self, source: usize, sink: usize) {
+        let n = self.adjacent.len();
+        loop {
+            let mut dist = vec![f64::INFINITY; n];
+            let mut via: Vec<Option<usize>> = vec![None; n];
+            dist[source] = 0.0;
+            for _ in 0..n {
+                let mut changed = false;
+                for u in 0..n {
+                    if dist[u] == f64::INFINITY {
+                        continue;
+                    }
+                    for &e in &self.adjacent[u] {
+                        let v = self.to[e];
+                        if self.capacity[e] > EPSILON && dist[u] + self.cost[e] < dist[v] - EPSILON
+                        {
+                            dist[v] = dist[u] + self.cost[e];
+                            via[v] = Some(e);
+                            changed = true;
+                        }
+                    }
+                }
+                if !changed {
+                    break;
+                }
+            }
+            if dist[sink] >= -EPSILON {
+                return;
+            }
+
+            // A simple path has fewer than `n` edges; f64 rounding can leave
+            // a predecessor cycle instead, and then no path can be trusted
+            let mut path = Vec::new();
+            let mut v = sink;
+            while let Some(e) = via[v] {
+                if path.len() >= n {
+                    return;
+                }
+                path.push(e);
+                v = self.to[e ^ 1];
+            }
+            if v != source {
+                return;
+            }
+            let push = path
+                .iter()
+                .map(|&e| self.capacity[e])
+                .fold(f64::INFINITY, f64::min);
+            for e in path {
+                self.capacity[e] -= push;
+                self.capacity[e ^ 1] += push;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone_order(
+        auction: &mut AuctionSimulator,
+        id: u32,
+        zone: u32,
+        price: f64,
+        amount: f64,
+        is_bid: bool,
+    ) {
+        auction.orders.push(AuctionOrderWasm {
+            id,
+            price,
+            amount,
+            is_bid,
+            period: 0,
+            zone,
+            participant: None,
+        });
+    }
+
+    #[test]
+    fn test_congested_interconnector_splits_prices() {
+        let mut auction = AuctionSimulator::new();
+        // Cheap power in zone 1, demand in zone 2
+        zone_order(&mut auction, 1, 1, 10.0, 100.0, false);
+        zone_order(&mut auction, 2, 2, 60.0, 50.0, false);
+        zone_order(&mut auction, 3, 2, 80.0, 70.0, true);
+        auction.add_interconnector(1, 2, 40.0).unwrap();
+        auction.add_interconnector(2, 1, 40.0).unwrap();
+
+        let result = auction.zonal_clearing().unwrap();
+        assert_eq!(
+            result.zones[0],
+            ZoneClearing {
+                zone: 1,
+                price: Some(10.0),
+                demand: 0.0,
+                supply: 40.0,
+                net_export: 40.0,
+            }
+        );
+        assert_eq!(result.zones[1].price, Some(60.0));
+        assert_eq!(
+            (result.zones[1].demand, result.zones[1].supply),
+            (70.0, 30.0)
+        );
+        assert!(result.flows[0].congested);
+        assert_eq!(result.flows[0].flow, 40.0);
+        assert_eq!(result.flows[1].flow, 0.0);
+        assert_eq!(result.congestion_rent, 40.0 * 50.0);
+
+        // With room to spare the zones couple into one price
+        auction.add_interconnector(1, 2, 100.0).unwrap();
+        let result = auction.zonal_clearing().unwrap();
+        assert!(!result.flows[0].congested);
+        assert_eq!(result.flows[0].flow, 70.0);
+        assert_eq!(result.zones[0].price, result.zones[1].price);
+        assert_eq!(result.congestion_rent, 0.0);
+    }
+
+    #[test]
+    fn test_flow_follows_cheapest_path_around_a_congested_line() {
+        let mut auction = AuctionSimulator::new();
+        zone_order(&mut auction, 1, 1, 20.0, 30.0, false);
+        zone_order(&mut auction, 2, 3, 50.0, 30.0, true);
+        // 10 fits directly; the rest goes round through zone 2
+        auction.add_interconnector(1, 3, 10.0).unwrap();
+        auction.add_interconnector(1, 2, 15.0).unwrap();
+        auction.add_interconnector(2, 3, 100.0).unwrap();
+        assert!(auction.add_interconnector(2, 2, 1.0).is_err());
+
+        let result = auction.zonal_clearing().unwrap();
+        let flows: Vec<f64> = result.flows.iter().map(|f| f.flow).collect();
+        assert_eq!(flows, vec![10.0, 15.0, 15.0]);
+        assert_eq!(result.zones[2].demand, 25.0);
+        // Zones 2 and 3 share a price; both links out of zone 1 are full
+        assert_eq!(result.zones[0].price, Some(20.0));
+        assert_eq!(result.zones[1].price, Some(50.0));
+        assert_eq!(result.zones[2].price, Some(50.0));
+        assert_eq!(result.congestion_rent, 25.0 * 30.0);
+    }
+
+    #[test]
+    fn test_predecessor_cycle_stops_augmenting() {
+        // Source 0 reaches a negative cycle between 2 and 3 on the way to sink 1
+        let mut network = Network::new(4);
+        network.add(0, 2, 1.0, 0.0);
+        let cycle = [network.add(2, 3, 1.0, -1.0), network.add(3, 2, 1.0, -1.0)];
+        network.add(3, 1, 1.0, -1.0);
+        network.maximise_welfare(0, 1);
+        assert!(cycle.iter().all(|&e| network.flow(e) == 0.0));
+    }
+}
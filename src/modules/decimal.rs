@@ -0,0 +1,465 @@
+//! Fixed-Point Decimal
+//!
+//! Signed decimal numbers stored as an integer number of units at a
+//! configurable scale (decimal places). Used for exact price and quantity
+//! accounting where `f64` rounding is not acceptable.
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+/// Maximum supported number of decimal places
+pub const MAX_SCALE: u8 = 18;
+
+/// Signed fixed-point decimal: `units / 10^scale`
+///
+/// Values with different scales compare and add exactly; arithmetic results
+/// take the larger of the two scales.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Decimal {
+    units: i64,
+    scale: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseDecimalError(String);
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid decimal: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseDecimalError {}
+
+fn pow10(exp: u8) -> i128 {
+    10i128.pow(exp as u32)
+}
+
+/// Re-express `units` from one scale to another, rounding half away from zero
+fn round_units(units: i128, from: u8, to: u8) -> Option<i128> {
+    match to.cmp(&from) {
+        Ordering::Equal => Some(units),
+        Ordering::Greater => units.checked_mul(pow10(to - from)),
+        Ordering::Less => {
+            let divisor = pow10(from - to);
+            let half = divisor / 2;
+            Some(if units >= 0 {
+                (units + half) / divisor
+            } else {
+                (units - half) / divisor
+            })
+        }
+    }
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal { units: 0, scale: 0 };
+
+    /// Create from raw units, e.g. `Decimal::new(12345, 2)` is `123.45`
+    pub const fn new(units: i64, scale: u8) -> Self {
+        Self { units, scale }
+    }
+
+    pub const fn units(self) -> i64 {
+        self.units
+    }
+
+    pub const fn scale(self) -> u8 {
+        self.scale
+    }
+
+    /// Convert from `f64`, rounding half away from zero to `scale` places.
+    /// Returns `None` for NaN, infinities and values out of range.
+    pub fn from_f64(value: f64, scale: u8) -> Option<Self> {
+        if !value.is_finite() || scale > MAX_SCALE {
+            return None;
+        }
+        let scaled = (value * pow10(scale) as f64).round();
+        if scaled < i64::MIN as f64 || scaled >= i64::MAX as f64 {
+            return None;
+        }
+        Some(Self::new(scaled as i64, scale))
+    }
+
+    /// Lossy conversion for display and charting
+    pub fn to_f64(self) -> f64 {
+        self.units as f64 / pow10(self.scale) as f64
+    }
+
+    /// Express at `scale` places, rounding half away from zero when reducing
+    /// precision. Returns `None` if the result does not fit.
+    pub fn round_to(self, scale: u8) -> Option<Self> {
+        if scale > MAX_SCALE {
+            return None;
+        }
+        let units = round_units(self.units as i128, self.scale, scale)?;
+        i64::try_from(units)
+            .ok()
+            .map(|units| Self::new(units, scale))
+    }
+
+    /// Express at `scale` places only if no precision is lost
+    pub fn rescale(self, scale: u8) -> Option<Self> {
+        let rescaled = self.round_to(scale)?;
+        (rescaled == self).then_some(rescaled)
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (self.aligned(scale), other.aligned(scale));
+        i64::try_from(a + b)
+            .ok()
+            .map(|units| Self::new(units, scale))
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (self.aligned(scale), other.aligned(scale));
+        i64::try_from(a - b)
+            .ok()
+            .map(|units| Self::new(units, scale))
+    }
+
+    /// Sum clamped to the largest magnitude representable at the result
+    /// scale, for running totals with no natural bound such as traded volume
+    pub fn saturating_add(self, other: Self) -> Self {
+        self.checked_add(other).unwrap_or_else(|| {
+            let scale = self.scale.max(other.scale);
+            let units = if self.aligned(scale) + other.aligned(scale) > 0 {
+                i64::MAX
+            } else {
+                i64::MIN
+            };
+            Self::new(units, scale)
+        })
+    }
+
+    /// Exact product at `self.scale + other.scale` places
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        self.mul_round(other, self.scale.checked_add(other.scale)?)
+    }
+
+    /// Product rounded half away from zero to `scale` places, e.g. a notional
+    /// value expressed at price precision
+    pub fn mul_round(self, other: Self, scale: u8) -> Option<Self> {
+        if scale > MAX_SCALE {
+            return None;
+        }
+        let product = (self.units as i128).checked_mul(other.units as i128)?;
+        let units = round_units(product, self.scale + other.scale, scale)?;
+        i64::try_from(units)
+            .ok()
+            .map(|units| Self::new(units, scale))
+    }
+
+    /// Quotient rounded half away from zero to `scale` places, e.g. an
+    /// average price. Returns `None` when dividing by zero or on overflow.
+    pub fn div_round(self, other: Self, scale: u8) -> Option<Self> {
+        if other.is_zero() || scale > MAX_SCALE {
+            return None;
+        }
+        // (a / 10^sa) / (b / 10^sb) * 10^scale = a * 10^(scale + sb - sa) / b
+        let shift = scale as i32 + other.scale as i32 - self.scale as i32;
+        let (numerator, denominator) = if shift >= 0 {
+            (
+                (self.units as i128).checked_mul(pow10(shift as u8))?,
+                other.units as i128,
+            )
+        } else {
+            (
+                self.units as i128,
+                (other.units as i128).checked_mul(pow10((-shift) as u8))?,
+            )
+        };
+        let mut quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        if remainder.unsigned_abs() * 2 >= denominator.unsigned_abs() {
+            quotient += if (numerator < 0) == (denominator < 0) {
+                1
+            } else {
+                -1
+            };
+        }
+        i64::try_from(quotient)
+            .ok()
+            .map(|units| Self::new(units, scale))
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.units == 0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.units > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.units < 0
+    }
+
+    pub fn abs(self) -> Self {
+        Self::new(self.units.saturating_abs(), self.scale)
+    }
+
+    fn aligned(self, scale: u8) -> i128 {
+        self.units as i128 * pow10(scale - self.scale)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        self.aligned(scale).cmp(&other.aligned(scale))
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Panics on overflow (only `i64::MIN` units have no negation); see `Add`
+impl Neg for Decimal {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(
+            self.units.checked_neg().expect("decimal overflow"),
+            self.scale,
+        )
+    }
+}
+
+/// Panics on overflow, as integer arithmetic does in debug builds. Engine
+/// code keeps operands bounded (see `MAX_ORDER_UNITS` in the order book) and
+/// uses the checked or saturating forms for open-ended totals.
+impl Add for Decimal {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.checked_add(other).expect("decimal overflow")
+    }
+}
+
+/// Panics on overflow; see `Add`
+impl Sub for Decimal {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.checked_sub(other).expect("decimal overflow")
+    }
+}
+
+impl AddAssign for Decimal {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Decimal {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Decimal {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Decimal::ZERO, Add::add)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let divisor = pow10(self.scale) as u128;
+        let magnitude = (self.units as i128).unsigned_abs();
+        let sign = if self.units < 0 { "-" } else { "" };
+        if self.scale == 0 {
+            write!(f, "{}{}", sign, magnitude)
+        } else {
+            write!(
+                f,
+                "{}{}.{:0width$}",
+                sign,
+                magnitude / divisor,
+                magnitude % divisor,
+                width = self.scale as usize
+            )
+        }
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    /// Parse plain decimal notation such as `-12.500`; the scale is the
+    /// number of fractional digits given.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDecimalError(s.to_string());
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if (int_part.is_empty() && frac_part.is_empty())
+            || !all_digits(int_part)
+            || !all_digits(frac_part)
+            || frac_part.len() > MAX_SCALE as usize
+        {
+            return Err(err());
+        }
+
+        let mut units: i128 = 0;
+        for b in int_part.bytes().chain(frac_part.bytes()) {
+            units = units
+                .checked_mul(10)
+                .and_then(|u| u.checked_add((b - b'0') as i128))
+                .ok_or_else(err)?;
+        }
+        if negative {
+            units = -units;
+        }
+        let units = i64::try_from(units).map_err(|_| err())?;
+        Ok(Self::new(units, frac_part.len() as u8))
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct DecimalVisitor;
+
+impl Visitor<'_> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a decimal string or number")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+        // Display yields the shortest representation that round-trips
+        self.visit_str(&v.to_string())
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+        Ok(Decimal::new(v, 0))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+        i64::try_from(v)
+            .map(|units| Decimal::new(units, 0))
+            .map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display_round_trip() {
+        for s in ["0", "123.45", "-0.001", "-42", "7.000000"] {
+            let d: Decimal = s.parse().unwrap();
+            assert_eq!(d.to_string(), s);
+        }
+        assert!("".parse::<Decimal>().is_err());
+        assert!("1.2.3".parse::<Decimal>().is_err());
+        assert!("1e5".parse::<Decimal>().is_err());
+    }
+
+    #[test]
+    fn test_from_f64_keeps_sign_and_rounds() {
+        assert_eq!(Decimal::from_f64(-12.5, 2), Some(Decimal::new(-1250, 2)));
+        assert_eq!(
+            Decimal::from_f64(0.1 + 0.2, 6),
+            Some(Decimal::new(300_000, 6))
+        );
+        assert_eq!(Decimal::from_f64(f64::NAN, 6), None);
+    }
+
+    #[test]
+    fn test_mixed_scale_comparison_and_arithmetic() {
+        let a = Decimal::new(15, 1); // 1.5
+        let b = Decimal::new(1500, 3); // 1.500
+        assert_eq!(a, b);
+        assert!(Decimal::new(-1, 0) < Decimal::new(-5, 1));
+        assert_eq!(a + Decimal::new(25, 3), Decimal::new(1525, 3));
+        assert_eq!(
+            Decimal::new(1525, 3).round_to(2),
+            Some(Decimal::new(153, 2))
+        );
+        assert_eq!(Decimal::new(1525, 3).rescale(2), None);
+
+        let max = Decimal::new(i64::MAX, 2);
+        assert_eq!(max.checked_add(Decimal::new(1, 2)), None);
+        assert_eq!(max.saturating_add(Decimal::new(1, 0)), max);
+        assert_eq!(
+            (-max).saturating_add(Decimal::new(-1, 2)),
+            Decimal::new(i64::MIN, 2)
+        );
+
+        // The one value without a negation
+        let min = Decimal::new(i64::MIN, 2);
+        assert_eq!(min.checked_sub(min), Some(Decimal::ZERO));
+        assert_eq!(Decimal::ZERO.checked_sub(min), None);
+        assert!(std::panic::catch_unwind(|| -min).is_err());
+    }
+
+    #[test]
+    fn test_mul_round_avoids_overflow_at_combined_scale() {
+        let price = Decimal::new(150_000_000, 6); // 150.000000
+        let quantity = Decimal::new(2_000_000_000_000_000, 6); // 2e9
+        assert_eq!(price.checked_mul(quantity), None);
+        assert_eq!(
+            price.mul_round(quantity, 2),
+            Some(Decimal::new(30_000_000_000_000, 2))
+        );
+        assert_eq!(
+            Decimal::new(-125, 2).mul_round(Decimal::new(3, 0), 1),
+            Some(Decimal::new(-38, 1))
+        );
+    }
+
+    #[test]
+    fn test_div_round() {
+        let total: Decimal = "1001.5".parse().unwrap();
+        let volume: Decimal = "10".parse().unwrap();
+        assert_eq!(total.div_round(volume, 2), Some(Decimal::new(10015, 2)));
+        assert_eq!(
+            Decimal::new(2, 0).div_round(Decimal::new(3, 0), 3),
+            Some(Decimal::new(667, 3))
+        );
+        assert_eq!(
+            Decimal::new(-1, 0).div_round(Decimal::new(8, 0), 2),
+            Some(Decimal::new(-13, 2))
+        );
+        assert_eq!(Decimal::new(1, 0).div_round(Decimal::ZERO, 2), None);
+    }
+}
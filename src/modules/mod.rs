@@ -3,6 +3,7 @@ pub mod auction;
 pub mod bezier;
 pub mod clustering;
 pub mod crypto;
+pub mod decimal;
 pub mod governance;
 pub mod orderbook;
 pub mod portfolio;
@@ -2,11 +2,56 @@
 //!
 //! Client-side order book for visualization and matching preview.
 //! OPTIMIZED: Uses BTreeMap for O(log n) operations instead of Vec O(n)
+//!
+//! Prices and quantities are fixed-point `Decimal`s at a per-book precision,
+//! so fills conserve quantity exactly and negative prices are supported.
 
+use super::decimal::{Decimal, MAX_SCALE};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use wasm_bindgen::prelude::*;
 
+mod allocation;
+mod analytics;
+mod binary;
+mod call_auction;
+mod expiry;
+mod impact;
+mod journal;
+mod market_data;
+mod registry;
+mod risk;
+mod stops;
+mod trades;
+
+pub use allocation::{AllocationAlgorithm, AllocationPolicy};
+pub use analytics::BookAnalytics;
+pub use call_auction::{IndicativeUncross, TradingPhase};
+pub use expiry::TimeInForce;
+pub use impact::{ImpactEstimate, ImpactLevel};
+pub use journal::{BookCommand, CommandLog, LoggedCommand};
+pub use market_data::{BookSnapshot, LevelUpdate, MarketDataUpdate, OrderEvent};
+pub use registry::{ClosedInstrument, InstrumentId, MarketRegistry, TopOfBook};
+use risk::RiskBook;
+pub use risk::{AccountRisk, RiskLimits};
+use stops::StopBook;
+use trades::TradeHistory;
+pub use trades::{Candle, TradeStats};
+
+/// Default decimal places for prices and quantities
+const DEFAULT_SCALE: u8 = 6;
+
+/// Largest price or quantity an order may carry, in units at the book's
+/// precision. Sums of a few such values (a spread, a band around the last
+/// price, display plus reserve) then cannot overflow; totals over many orders
+/// or trades are checked or saturate instead.
+const MAX_ORDER_UNITS: i64 = 1_000_000_000_000_000;
+
+fn within_bounds(value: Decimal) -> bool {
+    value.units().abs() <= MAX_ORDER_UNITS
+}
+
 // ============================================================================
 // Types
 // ============================================================================
@@ -27,64 +72,362 @@ impl From<u8> for Side {
     }
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+/// Execution instruction attached to an order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum OrderType {
+    /// Rests in the book at its limit price until matched or cancelled
+    #[default]
+    Limit = 0,
+    /// Takes liquidity at any price, never rests
+    Market = 1,
+    /// Takes liquidity up to its limit price, remainder is discarded
+    ImmediateOrCancel = 2,
+    /// Fills completely up to its limit price or not at all
+    FillOrKill = 3,
+}
+
+impl From<u8> for OrderType {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => OrderType::Market,
+            2 => OrderType::ImmediateOrCancel,
+            3 => OrderType::FillOrKill,
+            _ => OrderType::Limit,
+        }
+    }
+}
+
+/// How the book resolves an order that would trade with another order from
+/// the same owner. "Newest" is the later-arriving (aggressing) order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum SelfTradePrevention {
+    /// Cancel the remainder of the newer order
+    #[default]
+    CancelNewest = 0,
+    /// Cancel the resting older order and keep matching the newer one
+    CancelOldest = 1,
+    /// Cancel both orders
+    CancelBoth = 2,
+    /// Reduce both orders by the smaller quantity, cancelling the smaller
+    DecrementAndCancel = 3,
+}
+
+impl From<u8> for SelfTradePrevention {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => SelfTradePrevention::CancelOldest,
+            2 => SelfTradePrevention::CancelBoth,
+            3 => SelfTradePrevention::DecrementAndCancel,
+            _ => SelfTradePrevention::CancelNewest,
+        }
+    }
+}
+
+impl SelfTradePrevention {
+    /// Quantities to cancel from the (newer, older) orders of a self-trade
+    fn cuts(self, newer: Decimal, older: Decimal) -> (Decimal, Decimal) {
+        match self {
+            SelfTradePrevention::CancelNewest => (newer, Decimal::ZERO),
+            SelfTradePrevention::CancelOldest => (Decimal::ZERO, older),
+            SelfTradePrevention::CancelBoth => (newer, older),
+            SelfTradePrevention::DecrementAndCancel => {
+                let smaller = newer.min(older);
+                (smaller, smaller)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Order {
     pub id: u32,
     pub side: Side,
-    pub price: f64,
-    pub quantity: f64,
+    pub price: Decimal,
+    pub quantity: Decimal,
     pub timestamp: u64,
+    #[serde(default)]
+    pub order_type: OrderType,
+    /// Arrival sequence assigned by the book when the order rests;
+    /// lower values have queue priority and act as makers
+    #[serde(default)]
+    pub sequence: u64,
+    /// Account that owns the order; orders from the same owner never trade
+    /// with each other
+    #[serde(default)]
+    pub owner: Option<u32>,
+    /// Trigger for stop orders: the order waits in the trigger book until the
+    /// last trade reaches this price, then enters as its `order_type`
+    /// (Market for a stop, Limit for a stop-limit)
+    #[serde(default)]
+    pub stop_price: Option<Decimal>,
+    /// Peak size shown in the book for an iceberg order; the rest of a
+    /// resting order's size is held back in `hidden_quantity`
+    #[serde(default)]
+    pub display_quantity: Option<Decimal>,
+    /// Hidden reserve of a resting iceberg, reloaded as the displayed
+    /// `quantity` fills
+    #[serde(default)]
+    pub hidden_quantity: Decimal,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    /// Time (ms) at which the order expires; required for GoodTillTime and
+    /// filled in by the book for GoodForDeliveryPeriod
+    #[serde(default)]
+    pub expires_at: Option<u64>,
 }
 
 impl Order {
-    pub fn new(id: u32, side: Side, price: f64, quantity: f64, timestamp: u64) -> Self {
+    pub fn new(id: u32, side: Side, price: Decimal, quantity: Decimal, timestamp: u64) -> Self {
         Self {
             id,
             side,
             price,
             quantity,
             timestamp,
+            order_type: OrderType::Limit,
+            sequence: 0,
+            owner: None,
+            stop_price: None,
+            display_quantity: None,
+            hidden_quantity: Decimal::ZERO,
+            time_in_force: TimeInForce::GoodTillCancel,
+            expires_at: None,
+        }
+    }
+
+    /// Displayed plus hidden open size
+    fn total_quantity(&self) -> Decimal {
+        self.quantity + self.hidden_quantity
+    }
+
+    /// Show at most the peak size, moving the rest into the hidden reserve
+    fn split_display(&mut self) {
+        if let Some(peak) = self.display_quantity {
+            if self.quantity > peak {
+                self.hidden_quantity += self.quantity - peak;
+                self.quantity = peak;
+            }
+        }
+    }
+
+    /// Reload an exhausted displayed slice from the hidden reserve
+    fn replenish(&mut self) {
+        let slice = self
+            .display_quantity
+            .unwrap_or(self.hidden_quantity)
+            .min(self.hidden_quantity);
+        self.quantity = slice;
+        self.hidden_quantity -= slice;
+    }
+
+    /// Shrink the total open size, taking from the hidden reserve first
+    fn reduce_to(&mut self, total: Decimal) {
+        let shown = self.quantity.min(total);
+        self.hidden_quantity = total - shown;
+        self.quantity = shown;
+    }
+
+    /// Copy safe to publish: iceberg reserve and peak size are not revealed
+    fn public(&self) -> Order {
+        Order {
+            display_quantity: None,
+            hidden_quantity: Decimal::ZERO,
+            ..*self
+        }
+    }
+
+    /// Whether a trade at `last` fires this order's stop
+    fn triggers(&self, last: Decimal) -> bool {
+        match (self.stop_price, self.side) {
+            (None, _) => false,
+            (Some(stop), Side::Buy) => last >= stop,
+            (Some(stop), Side::Sell) => last <= stop,
+        }
+    }
+
+    fn same_owner(&self, other: &Order) -> bool {
+        self.owner.is_some() && self.owner == other.owner
+    }
+
+    /// Whether this order is willing to trade against a resting order at `price`
+    fn accepts(&self, price: Decimal) -> bool {
+        match (self.order_type, self.side) {
+            (OrderType::Market, _) => true,
+            (_, Side::Buy) => price <= self.price,
+            (_, Side::Sell) => price >= self.price,
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Match {
     pub buy_order_id: u32,
     pub sell_order_id: u32,
-    pub price: f64,
-    pub quantity: f64,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    /// Timestamp of the aggressing order
+    #[serde(default)]
+    pub timestamp: u64,
+    /// Accounts (order owners) on each side
+    #[serde(default)]
+    pub buy_owner: Option<u32>,
+    #[serde(default)]
+    pub sell_owner: Option<u32>,
 }
 
-// Helper for reverse ordering in BTreeMap
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-struct ReversePrice(u64);
+/// Trading rules for the instrument a book represents; unset fields are not enforced
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InstrumentRules {
+    /// Prices must be a multiple of this
+    #[serde(default)]
+    pub tick_size: Option<Decimal>,
+    /// Quantities must be a multiple of this
+    #[serde(default)]
+    pub lot_size: Option<Decimal>,
+    /// Minimum `|price| * quantity`
+    #[serde(default)]
+    pub min_notional: Option<Decimal>,
+    /// Maximum `|price| * quantity`
+    #[serde(default)]
+    pub max_notional: Option<Decimal>,
+    /// Maximum absolute distance from the last trade price
+    #[serde(default)]
+    pub price_band: Option<Decimal>,
+}
+
+/// Why an order was refused by the book
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "reason")]
+pub enum RejectReason {
+    /// Price is not a finite number representable at the book's precision
+    InvalidPrice,
+    /// Quantity is not a positive number representable at the book's precision
+    InvalidQuantity,
+    /// An order with the same id is already resting
+    DuplicateOrderId,
+    TickSize {
+        tick_size: Decimal,
+    },
+    LotSize {
+        lot_size: Decimal,
+    },
+    BelowMinNotional {
+        notional: Decimal,
+        min_notional: Decimal,
+    },
+    AboveMaxNotional {
+        notional: Decimal,
+        max_notional: Decimal,
+    },
+    OutsidePriceBand {
+        low: Decimal,
+        high: Decimal,
+    },
+    /// The account's open order notional would exceed its limit
+    OpenNotionalLimit {
+        account: u32,
+        limit: Decimal,
+        open_notional: Decimal,
+    },
+    /// The account's net position, with open orders filled, would exceed its limit
+    PositionLimit {
+        account: u32,
+        limit: Decimal,
+        position: Decimal,
+    },
+    /// GoodTillTime without `expires_at`, or GoodForDeliveryPeriod before
+    /// the book's gate closure is set
+    MissingExpiry,
+    /// Expiry is not after the current time
+    Expired {
+        expires_at: u64,
+    },
+    /// The book's trading phase does not accept this order or amendment
+    NotAllowedInPhase {
+        phase: TradingPhase,
+    },
+    /// The account's totals with this order do not fit the book's precision
+    Overflow,
+}
+
+impl From<RejectReason> for JsValue {
+    fn from(reason: RejectReason) -> Self {
+        serde_wasm_bindgen::to_value(&reason).unwrap_or_else(JsValue::from)
+    }
+}
+
+/// Quantity removed from an order by self-trade prevention
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StpCancel {
+    pub order_id: u32,
+    pub quantity: Decimal,
+}
+
+/// Fills and side effects produced by a matching pass
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MatchReport {
+    pub matches: Vec<Match>,
+    /// Orders reduced or cancelled by self-trade prevention
+    pub stp_cancelled: Vec<StpCancel>,
+    /// Stop orders whose trigger fired and that entered matching
+    pub triggered_stops: Vec<u32>,
+    /// Orders purged because they expired before matching
+    pub expired: Vec<u32>,
+    // Changes to resting orders, collected when market data is on
+    #[serde(skip)]
+    events: Vec<OrderEvent>,
+}
 
-impl ReversePrice {
-    fn from_f64(price: f64) -> Self {
-        // Store as fixed-point to avoid floating point issues in ordering
-        Self((price * 1_000_000.0) as u64)
+impl MatchReport {
+    fn absorb(&mut self, other: MatchReport) {
+        self.matches.extend(other.matches);
+        self.stp_cancelled.extend(other.stp_cancelled);
+        self.triggered_stops.extend(other.triggered_stops);
+        self.expired.extend(other.expired);
+        self.events.extend(other.events);
     }
 
-    fn to_f64(&self) -> f64 {
-        self.0 as f64 / 1_000_000.0
+    fn record_stp(&mut self, order_id: u32, quantity: Decimal) {
+        if quantity.is_positive() {
+            self.stp_cancelled.push(StpCancel { order_id, quantity });
+        }
     }
 }
 
-// Helper for normal ordering in BTreeMap
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-struct Price(u64);
+/// Outcome of submitting an order for continuous matching
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SubmitResult {
+    pub matches: Vec<Match>,
+    pub stp_cancelled: Vec<StpCancel>,
+    pub triggered_stops: Vec<u32>,
+    pub expired: Vec<u32>,
+    /// Unfilled remainder left resting in the book, or the order itself when
+    /// parked as an untriggered stop
+    pub resting: Option<Order>,
+}
 
-impl Price {
-    fn from_f64(price: f64) -> Self {
-        Self((price * 1_000_000.0) as u64)
+// Helper for reverse ordering in BTreeMap
+// Keys hold price units at the book's price scale
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct ReversePrice(i64);
+
+// Highest price sorts first so `first_key_value` yields the best bid
+impl Ord for ReversePrice {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.cmp(&self.0)
     }
+}
 
-    fn to_f64(&self) -> f64 {
-        self.0 as f64 / 1_000_000.0
+impl PartialOrd for ReversePrice {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
+// Helper for normal ordering in BTreeMap
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Price(i64);
+
 // ============================================================================
 // Order Book - OPTIMIZED with BTreeMap
 // ============================================================================
@@ -98,7 +441,45 @@ pub struct OrderBook {
     // Key: Price level, Value: Vec of orders at that price (time-ordered)
     asks: BTreeMap<Price, Vec<Order>>,
     // Index for O(1) order lookup by ID
-    order_index: std::collections::HashMap<u32, (Side, u64)>, // (side, price_key)
+    order_index: HashMap<u32, (Side, i64)>, // (side, price_key)
+    // Next arrival sequence to stamp on a resting order
+    next_sequence: u64,
+    // Decimal places for prices and quantities in this book
+    price_scale: u8,
+    quantity_scale: u8,
+    // Order entry rules, stored at the book's precision
+    rules: InstrumentRules,
+    // Stop orders waiting for their trigger price
+    stops: StopBook,
+    // Latest time seen, when this book's delivery period closes, and
+    // (expires_at, id) of orders that can expire
+    clock: u64,
+    gate_closure: Option<u64>,
+    expiries: BTreeSet<(u64, u32)>,
+    // Recent fills, 24h totals and candles; the last price anchors the price band
+    trades: TradeHistory,
+    // Per-account limits and net positions from fills
+    risk: RiskBook,
+    // Continuous matching, or a call phase collecting orders for an uncross
+    phase: TradingPhase,
+    // Resolution for orders that would trade against the same owner
+    stp_mode: SelfTradePrevention,
+    // How a fill is shared among the orders at one price level
+    allocation: AllocationPolicy,
+    // Incremental market data: on/off, last published sequence, events of
+    // the operation in progress and updates not yet drained
+    market_data: bool,
+    feed_sequence: u64,
+    pending_events: Vec<OrderEvent>,
+    updates: Vec<MarketDataUpdate>,
+    // Commands applied since logging started, if logging
+    command_log: Option<CommandLog>,
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -108,333 +489,2128 @@ impl OrderBook {
         Self {
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
-            order_index: std::collections::HashMap::with_capacity(1000),
+            order_index: HashMap::with_capacity(1000),
+            next_sequence: 1,
+            price_scale: DEFAULT_SCALE,
+            quantity_scale: DEFAULT_SCALE,
+            rules: InstrumentRules::default(),
+            stops: StopBook::default(),
+            clock: 0,
+            gate_closure: None,
+            expiries: BTreeSet::new(),
+            trades: TradeHistory::default(),
+            risk: RiskBook::default(),
+            phase: TradingPhase::default(),
+            stp_mode: SelfTradePrevention::default(),
+            allocation: AllocationPolicy::default(),
+            market_data: false,
+            feed_sequence: 0,
+            pending_events: Vec::new(),
+            updates: Vec::new(),
+            command_log: None,
+        }
+    }
+
+    /// Create an empty book with the given decimal places for prices and quantities
+    pub fn with_precision(price_decimals: u8, quantity_decimals: u8) -> Result<OrderBook, JsValue> {
+        if price_decimals > MAX_SCALE || quantity_decimals > MAX_SCALE {
+            return Err(JsValue::from_str(&format!(
+                "Precision must be at most {} decimal places",
+                MAX_SCALE
+            )));
         }
+        Ok(Self {
+            price_scale: price_decimals,
+            quantity_scale: quantity_decimals,
+            ..Self::new()
+        })
+    }
+
+    /// Set tick size, lot size, notional limits and price band
+    /// Expects `{ tick_size?, lot_size?, min_notional?, max_notional?, price_band? }`
+    pub fn set_rules(&mut self, rules: JsValue) -> Result<(), JsValue> {
+        let rules: InstrumentRules = serde_wasm_bindgen::from_value(rules)?;
+        self.set_instrument_rules(rules)
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.journal(BookCommand::SetRules { rules: self.rules });
+        Ok(())
+    }
+
+    /// Get the current instrument rules
+    pub fn get_rules(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.rules)?)
+    }
+
+    /// Set the self-trade prevention mode
+    /// (0=CancelNewest, 1=CancelOldest, 2=CancelBoth, 3=DecrementAndCancel)
+    pub fn set_self_trade_prevention(&mut self, mode: u8) {
+        self.stp_mode = SelfTradePrevention::from(mode);
+        self.journal(BookCommand::SetSelfTradePrevention {
+            mode: self.stp_mode,
+        });
+    }
+
+    /// Set how fills are shared within a price level
+    /// Expects `{ algorithm: "Fifo" | "ProRata" | "SizeTime", top_order_priority?, fifo_percent? }`
+    pub fn set_allocation_policy(&mut self, policy: JsValue) -> Result<(), JsValue> {
+        let policy: AllocationPolicy = serde_wasm_bindgen::from_value(policy)?;
+        policy.validate().map_err(|e| JsValue::from_str(&e))?;
+        self.allocation = policy;
+        self.journal(BookCommand::SetAllocation { policy });
+        Ok(())
+    }
+
+    /// Get the current allocation policy
+    pub fn get_allocation_policy(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.allocation)?)
     }
 
     /// Clear all orders
     pub fn clear(&mut self) {
-        self.bids.clear();
-        self.asks.clear();
-        self.order_index.clear();
+        self.journal(BookCommand::Clear);
+        self.clear_orders();
     }
 
     /// Add an order to the book - O(log n) insertion
-    pub fn add_order(&mut self, id: u32, side: u8, price: f64, quantity: f64, timestamp: u64) {
-        let order = Order::new(id, Side::from(side), price, quantity, timestamp);
+    /// Prices and quantities are rounded to the book's precision. Throws a
+    /// `{ reason, ... }` object if the order breaks an instrument rule.
+    pub fn add_order(
+        &mut self,
+        id: u32,
+        side: u8,
+        price: f64,
+        quantity: f64,
+        timestamp: u64,
+    ) -> Result<(), JsValue> {
+        let order = self.order_from_f64(id, Side::from(side), price, quantity, timestamp)?;
+        self.journal(BookCommand::Add { order });
+        self.execute(order)?;
+        Ok(())
+    }
 
-        match order.side {
-            Side::Buy => {
-                let price_key = ReversePrice::from_f64(price);
-                self.bids
-                    .entry(price_key)
-                    .or_insert_with(Vec::new)
-                    .push(order);
-                self.order_index.insert(id, (Side::Buy, price_key.0));
-            }
-            Side::Sell => {
-                let price_key = Price::from_f64(price);
-                self.asks
-                    .entry(price_key)
-                    .or_insert_with(Vec::new)
-                    .push(order);
-                self.order_index.insert(id, (Side::Sell, price_key.0));
-            }
-        }
+    /// Add an order with an execution type (0=Limit, 1=Market, 2=IOC, 3=FOK)
+    ///
+    /// Limit orders rest in the book like `add_order`. Market, IOC and FOK
+    /// orders execute immediately against the opposite side and never rest;
+    /// any unfilled remainder is discarded.
+    /// Returns `{ matches, stp_cancelled, triggered_stops, expired }`.
+    pub fn add_order_with_type(
+        &mut self,
+        id: u32,
+        side: u8,
+        order_type: u8,
+        price: f64,
+        quantity: f64,
+        timestamp: u64,
+    ) -> Result<JsValue, JsValue> {
+        let mut order = self.order_from_f64(id, Side::from(side), price, quantity, timestamp)?;
+        order.order_type = OrderType::from(order_type);
+        self.journal(BookCommand::Add { order });
+        let report = self.execute(order)?;
+        Ok(serde_wasm_bindgen::to_value(&report)?)
+    }
+
+    /// Submit an order for continuous matching (0=Limit, 1=Market, 2=IOC, 3=FOK)
+    ///
+    /// The order is matched against the opposite side as it arrives, trading at
+    /// each resting order's price. A limit remainder rests in the book; other
+    /// types discard it.
+    /// Returns `{ matches, stp_cancelled, triggered_stops, expired, resting }`.
+    pub fn submit_order(
+        &mut self,
+        id: u32,
+        side: u8,
+        order_type: u8,
+        price: f64,
+        quantity: f64,
+        timestamp: u64,
+    ) -> Result<JsValue, JsValue> {
+        let mut order = self.order_from_f64(id, Side::from(side), price, quantity, timestamp)?;
+        order.order_type = OrderType::from(order_type);
+        self.journal(BookCommand::Submit { order });
+        let result = self.submit(order)?;
+        Ok(serde_wasm_bindgen::to_value(&result)?)
+    }
+
+    /// Submit a full order object for continuous matching
+    /// Expects `{ id, side, price, quantity, timestamp, order_type?, owner?, stop_price?,
+    /// display_quantity?, time_in_force?, expires_at? }` with `side` as "Buy"/"Sell",
+    /// `order_type` as "Limit", "Market", "ImmediateOrCancel" or "FillOrKill" and
+    /// `time_in_force` as "GoodTillCancel", "GoodTillTime" or "GoodForDeliveryPeriod".
+    ///
+    /// With `stop_price` set, the order is a stop (Market) or stop-limit
+    /// (Limit): it waits out of the visible book until the last trade price
+    /// reaches the stop (at or above for buys, at or below for sells), then
+    /// enters matching. It enters at once if the last trade is already
+    /// through the stop. The price band is not applied to stop orders.
+    ///
+    /// With `display_quantity` set, a resting remainder is an iceberg that
+    /// shows at most that size and keeps the rest hidden.
+    ///
+    /// GoodTillTime orders need `expires_at`; GoodForDeliveryPeriod orders
+    /// expire at the book's gate closure. Expired orders are purged before
+    /// matching and listed in `expired`.
+    /// Returns `{ matches, stp_cancelled, triggered_stops, expired, resting }`.
+    pub fn place_order(&mut self, order: JsValue) -> Result<JsValue, JsValue> {
+        let order: Order = serde_wasm_bindgen::from_value(order)?;
+        self.journal(BookCommand::Submit { order });
+        let result = self.submit(order)?;
+        Ok(serde_wasm_bindgen::to_value(&result)?)
     }
 
     /// Bulk load orders - optimized to avoid per-insert overhead
+    /// Prices and quantities may be numbers or decimal strings. Orders are
+    /// checked against tick, lot and notional rules; the book is left
+    /// unchanged if any order is rejected.
     pub fn load_orders(&mut self, orders: JsValue) -> Result<(), JsValue> {
         let orders_vec: Vec<Order> = serde_wasm_bindgen::from_value(orders)?;
-        self.clear();
-
-        // Group orders by price level for efficient batch insertion
+        let mut seen = HashSet::with_capacity(orders_vec.len());
+        let mut admitted = Vec::with_capacity(orders_vec.len());
         for order in orders_vec {
-            match order.side {
-                Side::Buy => {
-                    let price_key = ReversePrice::from_f64(order.price);
-                    self.bids
-                        .entry(price_key)
-                        .or_insert_with(Vec::new)
-                        .push(order);
-                    self.order_index.insert(order.id, (Side::Buy, price_key.0));
-                }
-                Side::Sell => {
-                    let price_key = Price::from_f64(order.price);
-                    self.asks
-                        .entry(price_key)
-                        .or_insert_with(Vec::new)
-                        .push(order);
-                    self.order_index.insert(order.id, (Side::Sell, price_key.0));
-                }
-            }
+            let order = self
+                .normalize(order)
+                .and_then(|order| self.resolve_expiry(order))
+                .and_then(|order| self.check_static_rules(&order).map(|_| order))
+                .and_then(|order| {
+                    if seen.insert(order.id) {
+                        Ok(order)
+                    } else {
+                        Err(RejectReason::DuplicateOrderId)
+                    }
+                })
+                .map_err(|reason| {
+                    JsValue::from_str(&format!("Order {} rejected: {:?}", order.id, reason))
+                })?;
+            admitted.push(order);
         }
+        self.journal(BookCommand::Load {
+            orders: admitted.clone(),
+        });
+        self.load(admitted);
         Ok(())
     }
 
     /// Cancel order - O(1) lookup with HashMap index
     pub fn cancel_order(&mut self, order_id: u32) -> bool {
-        // O(1) lookup using index
-        let Some((side, price_key)) = self.order_index.remove(&order_id) else {
-            return false;
-        };
+        self.journal(BookCommand::Cancel { order_id });
+        self.cancel(order_id)
+    }
 
-        match side {
-            Side::Buy => {
-                let key = ReversePrice(price_key);
-                if let Some(orders) = self.bids.get_mut(&key) {
-                    if let Some(pos) = orders.iter().position(|o| o.id == order_id) {
-                        orders.remove(pos);
-                        // Clean up empty price levels
-                        if orders.is_empty() {
-                            self.bids.remove(&key);
-                        }
-                        return true;
-                    }
-                }
-            }
-            Side::Sell => {
-                let key = Price(price_key);
-                if let Some(orders) = self.asks.get_mut(&key) {
-                    if let Some(pos) = orders.iter().position(|o| o.id == order_id) {
-                        orders.remove(pos);
-                        // Clean up empty price levels
-                        if orders.is_empty() {
-                            self.asks.remove(&key);
-                        }
-                        return true;
-                    }
-                }
-            }
-        }
+    /// Amend a resting order's price and open quantity
+    ///
+    /// Reducing quantity at the same price keeps the order's queue position.
+    /// A price change or quantity increase moves it to the back of the queue
+    /// at its (new) price level. Parked stop orders can be amended too: their
+    /// stop price stays, and a size increase or new limit price moves them
+    /// behind stops already waiting. Returns false if the order is unknown; throws
+    /// a `{ reason, ... }` object if the amended order breaks an instrument
+    /// rule. Use `cancel_order` to remove an order. For icebergs `quantity` is
+    /// the total displayed plus hidden size; reductions come out of the
+    /// hidden reserve first.
+    pub fn amend_order(
+        &mut self,
+        order_id: u32,
+        price: f64,
+        quantity: f64,
+    ) -> Result<bool, JsValue> {
+        self.journal(BookCommand::Amend {
+            order_id,
+            price,
+            quantity,
+        });
+        Ok(self.amend(order_id, price, quantity)?)
+    }
 
-        false
+    /// Get best bid price - O(1) with BTreeMap (undefined if empty)
+    pub fn best_bid_price(&self) -> Option<f64> {
+        self.best_bid().map(Decimal::to_f64)
     }
 
-    /// Get best bid price - O(1) with BTreeMap
-    pub fn best_bid_price(&self) -> f64 {
-        self.bids
-            .first_key_value()
-            .map(|(k, v)| if !v.is_empty() { k.to_f64() } else { -1.0 })
-            .unwrap_or(-1.0)
+    /// Get best ask price - O(1) with BTreeMap (undefined if empty)
+    pub fn best_ask_price(&self) -> Option<f64> {
+        self.best_ask().map(Decimal::to_f64)
     }
 
-    /// Get best ask price - O(1) with BTreeMap
-    pub fn best_ask_price(&self) -> f64 {
-        self.asks
-            .first_key_value()
-            .map(|(k, v)| if !v.is_empty() { k.to_f64() } else { -1.0 })
-            .unwrap_or(-1.0)
+    pub fn spread(&self) -> Option<f64> {
+        let (bid, ask) = (self.best_bid()?, self.best_ask()?);
+        Some((ask - bid).to_f64())
     }
 
-    pub fn spread(&self) -> f64 {
-        match (self.best_bid_price(), self.best_ask_price()) {
-            (bid, ask) if bid >= 0.0 && ask >= 0.0 => ask - bid,
-            _ => -1.0,
-        }
+    pub fn mid_price(&self) -> Option<f64> {
+        let (bid, ask) = (self.best_bid()?, self.best_ask()?);
+        Some((bid.to_f64() + ask.to_f64()) / 2.0)
     }
 
-    pub fn mid_price(&self) -> f64 {
-        match (self.best_bid_price(), self.best_ask_price()) {
-            (bid, ask) if bid >= 0.0 && ask >= 0.0 => (bid + ask) / 2.0,
-            _ => -1.0,
-        }
+    /// Price of the most recent fill (undefined before the first trade)
+    pub fn last_trade_price(&self) -> Option<f64> {
+        self.trades.last_price().map(Decimal::to_f64)
     }
 
-    /// Match orders - optimized with BTreeMap
-    pub fn match_orders(&mut self) -> Result<JsValue, JsValue> {
-        let mut matches = Vec::new();
+    /// Most recent `limit` fills, oldest first
+    pub fn get_trades(&self, limit: usize) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.trades.recent(limit))?)
+    }
 
-        loop {
-            // Get the best bid and ask by peeking at first entries
-            let best_bid_opt = self
-                .bids
-                .first_key_value()
-                .and_then(|(_, orders)| orders.first().map(|o| *o));
-            let best_ask_opt = self
-                .asks
-                .first_key_value()
-                .and_then(|(_, orders)| orders.first().map(|o| *o));
-
-            let (best_bid, best_ask) = match (best_bid_opt, best_ask_opt) {
-                (Some(bid), Some(ask)) => (bid, ask),
-                _ => break, // No more orders to match
-            };
+    /// Keep at most `limit` fills on the trade tape
+    pub fn set_trade_history_limit(&mut self, limit: usize) {
+        self.trades.set_tape_limit(limit);
+    }
 
-            if best_bid.price < best_ask.price {
-                break; // No more matches possible
-            }
+    /// Last price, 24h VWAP, volume and trade count as of `now` (ms)
+    /// Returns `{ last_price, vwap_24h, volume_24h, trade_count_24h }`
+    pub fn get_trade_stats(&self, now: u64) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.trades.stats(now, self.price_scale),
+        )?)
+    }
 
-            let exec_price = if best_bid.timestamp <= best_ask.timestamp {
-                best_bid.price
-            } else {
-                best_ask.price
-            };
+    /// OHLCV candles for an interval in ms, oldest first
+    /// Returns `[{ start, open, high, low, close, volume, trade_count }]`
+    pub fn get_candles(&self, interval_ms: u64) -> Result<JsValue, JsValue> {
+        let candles = self.trades.candles(interval_ms).ok_or_else(|| {
+            JsValue::from_str(&format!("No candles kept for interval {}ms", interval_ms))
+        })?;
+        Ok(serde_wasm_bindgen::to_value(&candles)?)
+    }
 
-            let exec_qty = best_bid.quantity.min(best_ask.quantity);
+    /// Replace the candle intervals (ms); existing candles are discarded
+    pub fn set_candle_intervals(&mut self, intervals_ms: Vec<u64>) -> Result<(), JsValue> {
+        self.trades
+            .set_intervals(&intervals_ms)
+            .map_err(|e| JsValue::from_str(&e))
+    }
 
-            matches.push(Match {
-                buy_order_id: best_bid.id,
-                sell_order_id: best_ask.id,
-                price: exec_price,
-                quantity: exec_qty,
-            });
+    /// Match crossed orders - optimized with BTreeMap
+    ///
+    /// The earlier-arriving order of each crossed pair is the maker and sets
+    /// the execution price. Crossed orders from the same owner are resolved
+    /// by the self-trade prevention mode instead of trading. Stop orders
+    /// triggered by the resulting trades enter matching in the same call.
+    /// Returns `{ matches, stp_cancelled, triggered_stops, expired }`.
+    pub fn match_orders(&mut self) -> Result<JsValue, JsValue> {
+        self.journal(BookCommand::Match);
+        let report = self.match_crossed();
+        Ok(serde_wasm_bindgen::to_value(&report)?)
+    }
 
-            let bid_remaining = best_bid.quantity - exec_qty;
-            let ask_remaining = best_ask.quantity - exec_qty;
+    /// Get depth data for visualization - optimized iteration
+    /// Returns: { bids: [[price, cum_qty], ...], asks: [[price, cum_qty], ...] }
+    pub fn get_depth(&self, levels: usize) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.depth(levels))?)
+    }
 
-            // Update or remove bid
-            let bid_key = ReversePrice::from_f64(best_bid.price);
-            if bid_remaining <= 0.0001 {
-                if let Some(orders) = self.bids.get_mut(&bid_key) {
-                    if !orders.is_empty() {
-                        orders.remove(0);
-                    }
-                    self.order_index.remove(&best_bid.id);
-                    if orders.is_empty() {
-                        self.bids.remove(&bid_key);
-                    }
-                }
-            } else {
-                if let Some(orders) = self.bids.get_mut(&bid_key) {
-                    if !orders.is_empty() {
-                        orders[0].quantity = bid_remaining;
-                    }
-                }
-            }
+    pub fn bid_count(&self) -> usize {
+        self.bids.values().map(|v| v.len()).sum()
+    }
 
-            // Update or remove ask
-            let ask_key = Price::from_f64(best_ask.price);
-            if ask_remaining <= 0.0001 {
-                if let Some(orders) = self.asks.get_mut(&ask_key) {
-                    if !orders.is_empty() {
-                        orders.remove(0);
-                    }
-                    self.order_index.remove(&best_ask.id);
-                    if orders.is_empty() {
-                        self.asks.remove(&ask_key);
-                    }
-                }
+    pub fn ask_count(&self) -> usize {
+        self.asks.values().map(|v| v.len()).sum()
+    }
+
+    /// Number of stop orders waiting for their trigger
+    pub fn stop_count(&self) -> usize {
+        self.stops.len()
+    }
+}
+
+impl OrderBook {
+    /// Remove every resting and stop order
+    fn clear_orders(&mut self) {
+        if self.market_data {
+            let removed: Vec<OrderEvent> = self
+                .bids
+                .values()
+                .chain(self.asks.values())
+                .flatten()
+                .map(|o| OrderEvent::Delete {
+                    order_id: o.id,
+                    side: o.side,
+                    price: o.price,
+                })
+                .collect();
+            self.pending_events.extend(removed);
+        }
+        self.bids.clear();
+        self.asks.clear();
+        self.order_index.clear();
+        self.stops.clear();
+        self.expiries.clear();
+        self.risk.clear_open();
+        self.publish();
+    }
+
+    /// Replace the book's contents with admitted orders, keeping their order
+    fn load(&mut self, orders: Vec<Order>) {
+        self.clear_orders();
+        for order in orders {
+            if order.stop_price.is_some() {
+                self.park(order);
             } else {
-                if let Some(orders) = self.asks.get_mut(&ask_key) {
-                    if !orders.is_empty() {
-                        orders[0].quantity = ask_remaining;
-                    }
-                }
+                self.rest(order);
             }
         }
-
-        Ok(serde_wasm_bindgen::to_value(&matches)?)
+        self.publish();
     }
 
-    /// Get depth data for visualization - optimized iteration
-    /// Returns: { bids: [[price, cum_qty], ...], asks: [[price, cum_qty], ...] }
-    pub fn get_depth(&self, levels: usize) -> Result<JsValue, JsValue> {
-        let mut bid_depth = Vec::with_capacity(levels);
-        let mut cumulative = 0.0;
-        let mut last_price = f64::NAN;
-        let mut count = 0;
-
-        // Iterate through price levels in order
-        for (price_key, orders) in self.bids.iter() {
-            if count >= levels {
-                break;
-            }
+    fn cancel(&mut self, order_id: u32) -> bool {
+        let found = self.unlink(order_id).is_some() || self.stops.remove(order_id).is_some();
+        self.sync_exposure(order_id);
+        self.publish();
+        found
+    }
 
-            let price = price_key.to_f64();
-            if price != last_price {
-                if !last_price.is_nan() {
-                    bid_depth.push((last_price, cumulative));
-                    count += 1;
-                }
-                last_price = price;
-            }
+    /// Store rules at the book's precision, rejecting inconsistent values
+    fn set_instrument_rules(&mut self, rules: InstrumentRules) -> Result<(), String> {
+        let at_scale = |value: Option<Decimal>, scale: u8, name: &str| {
+            value
+                .map(|v| {
+                    v.rescale(scale)
+                        .ok_or_else(|| format!("{} {} is finer than book precision", name, v))
+                })
+                .transpose()
+        };
+        let rules = InstrumentRules {
+            tick_size: at_scale(rules.tick_size, self.price_scale, "tick_size")?,
+            lot_size: at_scale(rules.lot_size, self.quantity_scale, "lot_size")?,
+            min_notional: at_scale(rules.min_notional, self.price_scale, "min_notional")?,
+            max_notional: at_scale(rules.max_notional, self.price_scale, "max_notional")?,
+            price_band: at_scale(rules.price_band, self.price_scale, "price_band")?,
+        };
 
-            for order in orders.iter().take(levels * 10 - bid_depth.len() * 10) {
-                cumulative += order.quantity;
+        if rules.tick_size.is_some_and(|t| !t.is_positive())
+            || rules.lot_size.is_some_and(|l| !l.is_positive())
+        {
+            return Err("tick_size and lot_size must be positive".to_string());
+        }
+        if rules.min_notional.is_some_and(Decimal::is_negative)
+            || rules.max_notional.is_some_and(Decimal::is_negative)
+            || rules.price_band.is_some_and(Decimal::is_negative)
+        {
+            return Err("notional limits and price_band must not be negative".to_string());
+        }
+        if let (Some(min), Some(max)) = (rules.min_notional, rules.max_notional) {
+            if min > max {
+                return Err("min_notional exceeds max_notional".to_string());
             }
         }
 
-        if !last_price.is_nan() && bid_depth.len() < levels {
-            bid_depth.push((last_price, cumulative));
-        }
+        self.rules = rules;
+        Ok(())
+    }
 
-        let mut ask_depth = Vec::with_capacity(levels);
-        cumulative = 0.0;
-        last_price = f64::NAN;
-        count = 0;
+    /// Normalize an incoming order and check it against every rule
+    fn admit(&self, order: Order) -> Result<Order, RejectReason> {
+        let order = self.resolve_expiry(self.normalize(order)?)?;
+        if !self.phase_accepts(&order) {
+            return Err(RejectReason::NotAllowedInPhase { phase: self.phase });
+        }
+        if self.order_index.contains_key(&order.id) || self.stops.contains(order.id) {
+            return Err(RejectReason::DuplicateOrderId);
+        }
+        self.check_static_rules(&order)?;
+        if order.stop_price.is_none() {
+            self.check_price_band(&order)?;
+        }
+        self.check_risk(&order)?;
+        Ok(order)
+    }
 
-        for (price_key, orders) in self.asks.iter() {
-            if count >= levels {
-                break;
+    /// Tick, lot and notional checks that do not depend on market state.
+    /// Market orders carry no price, so only the lot size (and the tick size
+    /// of a stop price) applies to them.
+    fn check_static_rules(&self, order: &Order) -> Result<(), RejectReason> {
+        let rules = &self.rules;
+        if let Some(lot_size) = rules.lot_size {
+            let off_lot = |quantity: Decimal| quantity.units() % lot_size.units() != 0;
+            if off_lot(order.quantity) || order.display_quantity.is_some_and(off_lot) {
+                return Err(RejectReason::LotSize { lot_size });
             }
-
-            let price = price_key.to_f64();
-            if price != last_price {
-                if !last_price.is_nan() {
-                    ask_depth.push((last_price, cumulative));
-                    count += 1;
-                }
-                last_price = price;
+        }
+        if let (Some(tick_size), Some(stop)) = (rules.tick_size, order.stop_price) {
+            if stop.units() % tick_size.units() != 0 {
+                return Err(RejectReason::TickSize { tick_size });
             }
-
-            for order in orders.iter().take(levels * 10 - ask_depth.len() * 10) {
-                cumulative += order.quantity;
+        }
+        if order.order_type == OrderType::Market {
+            return Ok(());
+        }
+        if let Some(tick_size) = rules.tick_size {
+            if order.price.units() % tick_size.units() != 0 {
+                return Err(RejectReason::TickSize { tick_size });
             }
         }
 
-        if !last_price.is_nan() && ask_depth.len() < levels {
-            ask_depth.push((last_price, cumulative));
+        if rules.min_notional.is_none() && rules.max_notional.is_none() {
+            return Ok(());
+        }
+        let notional = order
+            .price
+            .abs()
+            .mul_round(order.quantity, self.price_scale)
+            .ok_or(RejectReason::InvalidQuantity)?;
+        if let Some(min_notional) = rules.min_notional {
+            if notional < min_notional {
+                return Err(RejectReason::BelowMinNotional {
+                    notional,
+                    min_notional,
+                });
+            }
+        }
+        if let Some(max_notional) = rules.max_notional {
+            if notional > max_notional {
+                return Err(RejectReason::AboveMaxNotional {
+                    notional,
+                    max_notional,
+                });
+            }
         }
+        Ok(())
+    }
 
-        let result = DepthResult {
-            bids: bid_depth,
-            asks: ask_depth,
+    /// Limit prices must stay within the band around the last trade, if any
+    fn check_price_band(&self, order: &Order) -> Result<(), RejectReason> {
+        let (Some(band), Some(last)) = (self.rules.price_band, self.trades.last_price()) else {
+            return Ok(());
         };
+        if order.order_type == OrderType::Market {
+            return Ok(());
+        }
+        let (low, high) = (last.saturating_add(-band), last.saturating_add(band));
+        if order.price < low || order.price > high {
+            return Err(RejectReason::OutsidePriceBand { low, high });
+        }
+        Ok(())
+    }
 
-        Ok(serde_wasm_bindgen::to_value(&result)?)
+    /// Best `levels` price levels per side with cumulative quantity
+    fn depth(&self, levels: usize) -> DepthResult {
+        let cumulative = |book: &mut dyn Iterator<Item = (i64, &Vec<Order>)>| {
+            let mut total = Decimal::ZERO;
+            book.take(levels)
+                .map(|(price_key, orders)| {
+                    total = orders
+                        .iter()
+                        .fold(total, |t, o| t.saturating_add(o.quantity));
+                    (
+                        Decimal::new(price_key, self.price_scale).to_f64(),
+                        total.to_f64(),
+                    )
+                })
+                .collect()
+        };
+        DepthResult {
+            bids: cumulative(&mut self.bids.iter().map(|(k, o)| (k.0, o))),
+            asks: cumulative(&mut self.asks.iter().map(|(k, o)| (k.0, o))),
+        }
     }
 
-    pub fn bid_count(&self) -> usize {
-        self.bids.values().map(|v| v.len()).sum()
+    fn resting_order(&self, order_id: u32) -> Option<&Order> {
+        let &(side, price_key) = self.order_index.get(&order_id)?;
+        self.level(side, price_key)?
+            .iter()
+            .find(|o| o.id == order_id)
     }
 
-    pub fn ask_count(&self) -> usize {
-        self.asks.values().map(|v| v.len()).sum()
+    fn level(&self, side: Side, price_key: i64) -> Option<&Vec<Order>> {
+        match side {
+            Side::Buy => self.bids.get(&ReversePrice(price_key)),
+            Side::Sell => self.asks.get(&Price(price_key)),
+        }
     }
-}
 
-#[derive(Serialize)]
-struct DepthResult {
-    bids: Vec<(f64, f64)>,
-    asks: Vec<(f64, f64)>,
-}
+    fn level_mut(&mut self, side: Side, price_key: i64) -> Option<&mut Vec<Order>> {
+        match side {
+            Side::Buy => self.bids.get_mut(&ReversePrice(price_key)),
+            Side::Sell => self.asks.get_mut(&Price(price_key)),
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn best_bid(&self) -> Option<Decimal> {
+        self.bids
+            .first_key_value()
+            .map(|(k, _)| Decimal::new(k.0, self.price_scale))
+    }
 
-    #[test]
-    fn test_order_insertion() {
-        let mut book = OrderBook::new();
+    fn best_ask(&self) -> Option<Decimal> {
+        self.asks
+            .first_key_value()
+            .map(|(k, _)| Decimal::new(k.0, self.price_scale))
+    }
 
-        book.add_order(1, 0, 100.0, 10.0, 1);
-        book.add_order(2, 0, 101.0, 5.0, 2);
-        book.add_order(3, 1, 102.0, 8.0, 3);
+    /// Mid price at the book's precision
+    fn mid(&self) -> Option<Decimal> {
+        (self.best_bid()? + self.best_ask()?).div_round(Decimal::new(2, 0), self.price_scale)
+    }
 
-        assert_eq!(book.best_bid_price(), 101.0);
-        assert_eq!(book.best_ask_price(), 102.0);
+    /// Build an order from JS numbers at the book's precision
+    fn order_from_f64(
+        &self,
+        id: u32,
+        side: Side,
+        price: f64,
+        quantity: f64,
+        timestamp: u64,
+    ) -> Result<Order, RejectReason> {
+        let price = Decimal::from_f64(price, self.price_scale).ok_or(RejectReason::InvalidPrice)?;
+        let quantity = Decimal::from_f64(quantity, self.quantity_scale)
+            .ok_or(RejectReason::InvalidQuantity)?;
+        self.normalize(Order::new(id, side, price, quantity, timestamp))
     }
 
-    #[test]
-    fn test_cancel_order() {
-        let mut book = OrderBook::new();
+    /// Round price and quantity to the book's precision. Any hidden reserve
+    /// is folded back into `quantity`; it is split off again when the order rests.
+    fn normalize(&self, mut order: Order) -> Result<Order, RejectReason> {
+        order.price = order
+            .price
+            .round_to(self.price_scale)
+            .filter(|&p| within_bounds(p))
+            .ok_or(RejectReason::InvalidPrice)?;
+        order.quantity = order
+            .quantity
+            .checked_add(order.hidden_quantity)
+            .and_then(|q| q.round_to(self.quantity_scale))
+            .filter(|&q| q.is_positive() && within_bounds(q))
+            .ok_or(RejectReason::InvalidQuantity)?;
+        order.hidden_quantity = Decimal::ZERO;
+        order.stop_price = order
+            .stop_price
+            .map(|stop| {
+                stop.round_to(self.price_scale)
+                    .filter(|&s| within_bounds(s))
+                    .ok_or(RejectReason::InvalidPrice)
+            })
+            .transpose()?;
+        order.display_quantity = order
+            .display_quantity
+            .map(|peak| {
+                peak.round_to(self.quantity_scale)
+                    .filter(|&p| p.is_positive() && within_bounds(p))
+                    .ok_or(RejectReason::InvalidQuantity)
+            })
+            .transpose()?;
+        Ok(order)
+    }
 
-        book.add_order(1, 0, 100.0, 10.0, 1);
-        book.add_order(2, 0, 101.0, 5.0, 2);
+    /// Validate and apply an amendment; see `amend_order`
+    fn amend(&mut self, order_id: u32, price: f64, quantity: f64) -> Result<bool, RejectReason> {
+        let Some(&(side, price_key)) = self.order_index.get(&order_id) else {
+            return self.amend_stop(order_id, price, quantity);
+        };
+        if self.phase == TradingPhase::Uncross {
+            return Err(RejectReason::NotAllowedInPhase { phase: self.phase });
+        }
+        let amended = self.order_from_f64(order_id, side, price, quantity, 0)?;
+        self.check_static_rules(&amended)?;
+        if amended.price.units() != price_key {
+            self.check_price_band(&amended)?;
+        }
+        let (price, quantity) = (amended.price, amended.quantity);
+        if let Some(&order) = self.resting_order(order_id) {
+            self.check_risk(&Order {
+                price,
+                quantity,
+                hidden_quantity: Decimal::ZERO,
+                ..order
+            })?;
+        }
+
+        let resting = if price.units() == price_key {
+            self.level_mut(side, price_key)
+                .and_then(|orders| orders.iter_mut().find(|o| o.id == order_id))
+        } else {
+            None
+        };
+        if let Some(order) = resting {
+            if quantity <= order.total_quantity() {
+                order.reduce_to(quantity);
+                let event = OrderEvent::changed(order);
+                self.record(event);
+                self.sync_exposure(order_id);
+                self.publish();
+                return Ok(true);
+            }
+        }
+
+        let Some(mut order) = self.unlink(order_id) else {
+            return Ok(false);
+        };
+        order.price = price;
+        order.quantity = quantity;
+        order.hidden_quantity = Decimal::ZERO;
+        self.rest(order);
+        self.publish();
+        Ok(true)
+    }
+
+    /// Amend a parked stop. The stop price is kept; `price` replaces the
+    /// limit price of a stop-limit and is ignored for a stop-market order. A
+    /// size reduction at the same price keeps the stop's trigger priority.
+    fn amend_stop(
+        &mut self,
+        order_id: u32,
+        price: f64,
+        quantity: f64,
+    ) -> Result<bool, RejectReason> {
+        let Some(&stop) = self.stops.get(order_id) else {
+            return Ok(false);
+        };
+        if self.phase == TradingPhase::Uncross {
+            return Err(RejectReason::NotAllowedInPhase { phase: self.phase });
+        }
+        let parsed = self.order_from_f64(order_id, stop.side, price, quantity, 0)?;
+        let amended = Order {
+            price: match stop.order_type {
+                OrderType::Market => stop.price,
+                _ => parsed.price,
+            },
+            quantity: parsed.quantity,
+            hidden_quantity: Decimal::ZERO,
+            ..stop
+        };
+        self.check_static_rules(&amended)?;
+        self.check_risk(&amended)?;
+
+        self.stops.remove(order_id);
+        if amended.price == stop.price && amended.quantity <= stop.total_quantity() {
+            self.stops.insert(amended);
+            self.sync_exposure(order_id);
+        } else {
+            self.park(amended);
+        }
+        Ok(true)
+    }
+
+    /// Insert an order at the back of its price level, returning it as stamped.
+    /// Icebergs rest with only their peak size displayed.
+    fn rest(&mut self, mut order: Order) -> Order {
+        order.split_display();
+        order.sequence = self.next_sequence;
+        self.insert(order);
+        order
+    }
+
+    /// Append an already-stamped order to its level and index it
+    fn insert(&mut self, order: Order) {
+        self.next_sequence = self.next_sequence.max(order.sequence + 1);
+        let price_key = order.price.units();
+        match order.side {
+            Side::Buy => self
+                .bids
+                .entry(ReversePrice(price_key))
+                .or_default()
+                .push(order),
+            Side::Sell => self.asks.entry(Price(price_key)).or_default().push(order),
+        }
+        self.order_index.insert(order.id, (order.side, price_key));
+        self.track_expiry(&order);
+        self.sync_exposure(order.id);
+        self.record(OrderEvent::Add {
+            order: order.public(),
+        });
+    }
+
+    /// Remove a resting order from its level and the index - O(1) lookup
+    fn unlink(&mut self, order_id: u32) -> Option<Order> {
+        let (side, price_key) = self.order_index.remove(&order_id)?;
+        let order = match side {
+            Side::Buy => remove_from_level(&mut self.bids, ReversePrice(price_key), order_id),
+            Side::Sell => remove_from_level(&mut self.asks, Price(price_key), order_id),
+        }?;
+        self.record(OrderEvent::Delete {
+            order_id,
+            side,
+            price: order.price,
+        });
+        self.sync_exposure(order_id);
+        Some(order)
+    }
+
+    /// Route an order according to its type. Limit orders rest without
+    /// matching; every other type takes liquidity and never rests. Orders
+    /// expired at the order's timestamp are purged first on either path.
+    fn execute(&mut self, order: Order) -> Result<MatchReport, RejectReason> {
+        self.advance_clock(order.timestamp);
+        let mut expired = self.expire_ids();
+        match order.order_type {
+            OrderType::Limit if order.stop_price.is_none() => {
+                let order = self.admit(order)?;
+                self.rest(order);
+                self.publish();
+                Ok(MatchReport {
+                    expired,
+                    ..MatchReport::default()
+                })
+            }
+            _ => {
+                let result = self.submit(order)?;
+                expired.extend(result.expired);
+                Ok(MatchReport {
+                    matches: result.matches,
+                    stp_cancelled: result.stp_cancelled,
+                    triggered_stops: result.triggered_stops,
+                    expired,
+                    ..MatchReport::default()
+                })
+            }
+        }
+    }
+
+    /// Match an incoming order on arrival, then rest any limit remainder.
+    /// Stop orders are parked until triggered unless the last trade is
+    /// already through their stop.
+    fn submit(&mut self, order: Order) -> Result<SubmitResult, RejectReason> {
+        self.advance_clock(order.timestamp);
+        let mut order = self.admit(order)?;
+        let mut report = MatchReport {
+            expired: self.expire_ids(),
+            ..MatchReport::default()
+        };
+
+        let resting = match order.stop_price {
+            Some(_)
+                if !self
+                    .trades
+                    .last_price()
+                    .is_some_and(|last| order.triggers(last)) =>
+            {
+                Some(self.park(order))
+            }
+            stop => {
+                if stop.is_some() {
+                    report.triggered_stops.push(order.id);
+                    order.stop_price = None;
+                }
+                if self.in_call_phase() {
+                    // Only limit orders are admitted; they wait for the uncross
+                    Some(self.rest(order))
+                } else {
+                    let (entered, resting) = self.enter(order);
+                    report.absorb(entered);
+                    self.fire_stops(&mut report);
+                    resting
+                }
+            }
+        };
+        self.publish();
+
+        Ok(SubmitResult {
+            matches: report.matches,
+            stp_cancelled: report.stp_cancelled,
+            triggered_stops: report.triggered_stops,
+            expired: report.expired,
+            resting,
+        })
+    }
+
+    /// Match an admitted order and rest any limit remainder
+    fn enter(&mut self, mut order: Order) -> (MatchReport, Option<Order>) {
+        let mut report = MatchReport::default();
+        if order.order_type != OrderType::FillOrKill
+            || self.available_quantity(&order) >= order.quantity
+        {
+            report = self.take(&mut order);
+        }
+
+        let resting = if order.order_type == OrderType::Limit && order.quantity.is_positive() {
+            Some(self.rest(order))
+        } else {
+            None
+        };
+        (report, resting)
+    }
+
+    /// Hold a stop order in the trigger book, stamped in arrival order
+    fn park(&mut self, mut order: Order) -> Order {
+        order.sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.track_expiry(&order);
+        self.stops.insert(order);
+        self.sync_exposure(order.id);
+        order
+    }
+
+    /// Enter every stop triggered by the last trade, repeating while their
+    /// own fills trigger further stops
+    fn fire_stops(&mut self, report: &mut MatchReport) {
+        while let Some(last) = self.trades.last_price() {
+            let fired = self.stops.take_triggered(last);
+            if fired.is_empty() {
+                break;
+            }
+            for mut order in fired {
+                self.sync_exposure(order.id);
+                report.triggered_stops.push(order.id);
+                order.stop_price = None;
+                let (entered, _) = self.enter(order);
+                report.absorb(entered);
+            }
+        }
+    }
+
+    /// Match the best bid against the best ask until the book is uncrossed
+    fn match_crossed(&mut self) -> MatchReport {
+        if self.in_call_phase() {
+            // Crossed orders wait for the uncross
+            return MatchReport::default();
+        }
+        let mut report = MatchReport {
+            expired: self.expire_ids(),
+            ..MatchReport::default()
+        };
+        let matching = self.matching();
+
+        loop {
+            let best_bid = self.bids.values().next().and_then(|o| o.first().copied());
+            let best_ask = self.asks.values().next().and_then(|o| o.first().copied());
+            let (Some(bid), Some(ask)) = (best_bid, best_ask) else {
+                break;
+            };
+            if bid.price < ask.price {
+                break;
+            }
+
+            // The order that arrived first was resting; the later one is the aggressor
+            if bid.sequence < ask.sequence {
+                cross_front(
+                    &mut self.asks,
+                    &mut self.bids,
+                    &mut self.order_index,
+                    &mut self.next_sequence,
+                    matching,
+                    &mut report,
+                );
+            } else {
+                cross_front(
+                    &mut self.bids,
+                    &mut self.asks,
+                    &mut self.order_index,
+                    &mut self.next_sequence,
+                    matching,
+                    &mut report,
+                );
+            }
+        }
+
+        self.record_trades(&report.matches);
+        self.sync_matched(&report);
+        self.pending_events.append(&mut report.events);
+        self.fire_stops(&mut report);
+        self.publish();
+        report
+    }
+
+    /// Match an incoming order against the opposite side, best price first.
+    /// Executes at the resting order's price and reduces `taker.quantity`.
+    fn take(&mut self, taker: &mut Order) -> MatchReport {
+        let mut report = MatchReport::default();
+        let matching = self.matching();
+        match taker.side {
+            Side::Buy => take_from_levels(
+                &mut self.asks,
+                &mut self.order_index,
+                &mut self.next_sequence,
+                taker,
+                matching,
+                &mut report,
+            ),
+            Side::Sell => take_from_levels(
+                &mut self.bids,
+                &mut self.order_index,
+                &mut self.next_sequence,
+                taker,
+                matching,
+                &mut report,
+            ),
+        }
+        self.record_trades(&report.matches);
+        self.sync_matched(&report);
+        self.pending_events.append(&mut report.events);
+        report
+    }
+
+    fn matching(&self) -> Matching {
+        Matching {
+            stp: self.stp_mode,
+            allocation: self.allocation,
+            lot: self
+                .rules
+                .lot_size
+                .unwrap_or(Decimal::new(1, self.quantity_scale)),
+            events: self.market_data,
+        }
+    }
+
+    fn record_trades(&mut self, matches: &[Match]) {
+        for trade in matches {
+            self.trades.record(trade, self.price_scale);
+        }
+        self.record_positions(matches);
+    }
+
+    /// Quantity `taker` could actually fill against the opposite side
+    fn available_quantity(&self, taker: &Order) -> Decimal {
+        match taker.side {
+            Side::Buy => available_in_levels(&self.asks, taker, self.matching()),
+            Side::Sell => available_in_levels(&self.bids, taker, self.matching()),
+        }
+    }
+}
+
+/// Settings that govern how a taker trades through a price level
+#[derive(Clone, Copy, Debug)]
+struct Matching {
+    stp: SelfTradePrevention,
+    allocation: AllocationPolicy,
+    // Granularity of pro-rata shares
+    lot: Decimal,
+    // Whether to report changes to resting orders
+    events: bool,
+}
+
+/// Sum resting quantity within `taker`'s limit in priority order, stopping
+/// once the taker's full quantity is covered. Own orders are skipped when
+/// self-trade prevention cancels them; any other mode ends the sweep there,
+/// or before their level when fills within a level are shared.
+fn available_in_levels<K>(
+    levels: &BTreeMap<K, Vec<Order>>,
+    taker: &Order,
+    matching: Matching,
+) -> Decimal {
+    let skip_own = matching.stp == SelfTradePrevention::CancelOldest;
+    let fifo = matching.allocation.algorithm == AllocationAlgorithm::Fifo;
+    let mut available = Decimal::ZERO;
+    for orders in levels.values() {
+        match orders.first() {
+            Some(first) if taker.accepts(first.price) && available < taker.quantity => {}
+            _ => break,
+        }
+        if !fifo && !skip_own && orders.iter().any(|o| o.same_owner(taker)) {
+            break;
+        }
+        for order in orders {
+            if order.same_owner(taker) {
+                if skip_own {
+                    continue;
+                }
+                return available;
+            }
+            available = available.saturating_add(order.total_quantity());
+        }
+    }
+    available
+}
+
+/// Remove an order from the level at `key`, cleaning up the level if emptied
+fn remove_from_level<K: Ord>(
+    levels: &mut BTreeMap<K, Vec<Order>>,
+    key: K,
+    order_id: u32,
+) -> Option<Order> {
+    let orders = levels.get_mut(&key)?;
+    let pos = orders.iter().position(|o| o.id == order_id)?;
+    let order = orders.remove(pos);
+    if orders.is_empty() {
+        levels.remove(&key);
+    }
+    Some(order)
+}
+
+/// Fill the front order of `takers`' best level against the older orders at
+/// the best level of `makers`, as if it had just arrived.
+fn cross_front<T: Ord + Copy, M: Ord + Copy>(
+    takers: &mut BTreeMap<T, Vec<Order>>,
+    makers: &mut BTreeMap<M, Vec<Order>>,
+    index: &mut HashMap<u32, (Side, i64)>,
+    next_sequence: &mut u64,
+    matching: Matching,
+    report: &mut MatchReport,
+) {
+    let (Some(mut taker_level), Some(mut maker_level)) =
+        (takers.first_entry(), makers.first_entry())
+    else {
+        return;
+    };
+    let taker_orders = taker_level.get_mut();
+    let maker_orders = maker_level.get_mut();
+    let taker = &mut taker_orders[0];
+    let open = taker.quantity;
+
+    // Levels are in arrival order, so the older orders form a prefix
+    let eligible = maker_orders
+        .iter()
+        .take_while(|o| o.sequence < taker.sequence)
+        .count();
+    fill_level(
+        maker_orders,
+        eligible,
+        taker,
+        matching,
+        index,
+        next_sequence,
+        report,
+    );
+    if matching.events && taker.quantity != open {
+        report.events.push(OrderEvent::changed(taker));
+    }
+
+    if taker.quantity.is_zero() {
+        let mut taker = taker_orders.remove(0);
+        if taker.hidden_quantity.is_positive() {
+            requeue_replenished(&mut taker, next_sequence, matching, report);
+            taker_orders.push(taker);
+        } else {
+            index.remove(&taker.id);
+        }
+    }
+    if maker_orders.is_empty() {
+        maker_level.remove();
+    }
+    if taker_orders.is_empty() {
+        taker_level.remove();
+    }
+}
+
+/// Sweep `levels` in key order, filling `taker` against resting orders until
+/// it is filled or the next level is outside its limit.
+fn take_from_levels<K: Ord + Copy>(
+    levels: &mut BTreeMap<K, Vec<Order>>,
+    index: &mut HashMap<u32, (Side, i64)>,
+    next_sequence: &mut u64,
+    taker: &mut Order,
+    matching: Matching,
+    report: &mut MatchReport,
+) {
+    while taker.quantity.is_positive() {
+        let Some(mut entry) = levels.first_entry() else {
+            break;
+        };
+        let orders = entry.get_mut();
+        if !orders.first().is_some_and(|o| taker.accepts(o.price)) {
+            break;
+        }
+
+        let eligible = orders.len();
+        fill_level(
+            orders,
+            eligible,
+            taker,
+            matching,
+            index,
+            next_sequence,
+            report,
+        );
+        if orders.is_empty() {
+            entry.remove();
+        }
+    }
+}
+
+/// Fill `taker` against the first `eligible` orders of one price level,
+/// removing makers that are fully filled. Icebergs whose displayed slice is
+/// used up reload from their reserve at the back of the level.
+///
+/// Under FIFO, makers trade strictly in queue order and own orders are
+/// resolved by self-trade prevention when reached. Other allocation policies
+/// resolve the taker's own orders at the level first, then share the fill
+/// among the remaining makers.
+fn fill_level(
+    orders: &mut Vec<Order>,
+    eligible: usize,
+    taker: &mut Order,
+    matching: Matching,
+    index: &mut HashMap<u32, (Side, i64)>,
+    next_sequence: &mut u64,
+    report: &mut MatchReport,
+) {
+    let fifo = matching.allocation.algorithm == AllocationAlgorithm::Fifo;
+    let open: Vec<Decimal> = if matching.events {
+        orders[..eligible].iter().map(|o| o.quantity).collect()
+    } else {
+        Vec::new()
+    };
+    let mut shared = Vec::new();
+
+    for maker in orders[..eligible].iter_mut() {
+        if !taker.quantity.is_positive() {
+            break;
+        }
+        if maker.same_owner(taker) {
+            // Cancelling an iceberg takes its reserve too, so it cannot reload
+            let (taker_total, maker_total) = (taker.total_quantity(), maker.total_quantity());
+            let (taker_cut, maker_cut) = matching.stp.cuts(taker_total, maker_total);
+            report.record_stp(taker.id, taker_cut);
+            report.record_stp(maker.id, maker_cut);
+            taker.reduce_to(taker_total - taker_cut);
+            maker.reduce_to(maker_total - maker_cut);
+        } else if fifo {
+            let exec_qty = taker.quantity.min(maker.quantity);
+            trade(taker, maker, exec_qty, report);
+        } else {
+            shared.push(maker);
+        }
+    }
+
+    if !shared.is_empty() && taker.quantity.is_positive() {
+        let resting: Vec<Decimal> = shared.iter().map(|o| o.quantity).collect();
+        let shares = matching
+            .allocation
+            .allocate(taker.quantity, &resting, matching.lot);
+        for (maker, share) in shared.into_iter().zip(shares) {
+            if share.is_positive() {
+                trade(taker, maker, share, report);
+            }
+        }
+    }
+
+    for (order, quantity) in orders.iter().zip(open) {
+        if order.quantity != quantity {
+            report.events.push(OrderEvent::changed(order));
+        }
+    }
+    let mut replenished = Vec::new();
+    orders.retain(|o| {
+        if o.quantity.is_positive() {
+            return true;
+        }
+        if o.hidden_quantity.is_positive() {
+            replenished.push(*o);
+        } else {
+            index.remove(&o.id);
+        }
+        false
+    });
+    for mut order in replenished {
+        requeue_replenished(&mut order, next_sequence, matching, report);
+        orders.push(order);
+    }
+}
+
+/// Reload an iceberg's display and restamp it, losing time priority
+fn requeue_replenished(
+    order: &mut Order,
+    next_sequence: &mut u64,
+    matching: Matching,
+    report: &mut MatchReport,
+) {
+    order.replenish();
+    order.sequence = *next_sequence;
+    *next_sequence += 1;
+    if matching.events {
+        report.events.push(OrderEvent::Add {
+            order: order.public(),
+        });
+    }
+}
+
+/// Record a fill at the maker's price and reduce both orders
+fn trade(taker: &mut Order, maker: &mut Order, quantity: Decimal, report: &mut MatchReport) {
+    let (buy, sell) = match taker.side {
+        Side::Buy => (&*taker, &*maker),
+        Side::Sell => (&*maker, &*taker),
+    };
+    report.matches.push(Match {
+        buy_order_id: buy.id,
+        sell_order_id: sell.id,
+        price: maker.price,
+        quantity,
+        timestamp: taker.timestamp,
+        buy_owner: buy.owner,
+        sell_owner: sell.owner,
+    });
+    taker.quantity -= quantity;
+    maker.quantity -= quantity;
+}
+
+#[derive(Serialize)]
+struct DepthResult {
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_insertion() {
+        let mut book = OrderBook::new();
+
+        book.add_order(1, 0, 100.0, 10.0, 1).unwrap();
+        book.add_order(2, 0, 101.0, 5.0, 2).unwrap();
+        book.add_order(3, 1, 102.0, 8.0, 3).unwrap();
+
+        assert_eq!(book.best_bid_price(), Some(101.0));
+        assert_eq!(book.best_ask_price(), Some(102.0));
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let mut book = OrderBook::new();
+
+        book.add_order(1, 0, 100.0, 10.0, 1).unwrap();
+        book.add_order(2, 0, 101.0, 5.0, 2).unwrap();
 
         assert!(book.cancel_order(1));
         assert!(!book.cancel_order(999));
         assert_eq!(book.bid_count(), 1);
     }
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn order(id: u32, side: Side, price: &str, quantity: &str) -> Order {
+        Order::new(id, side, dec(price), dec(quantity), id as u64)
+    }
+
+    fn typed(id: u32, side: Side, order_type: OrderType, price: &str, quantity: &str) -> Order {
+        let mut order = order(id, side, price, quantity);
+        order.order_type = order_type;
+        order
+    }
+
+    #[test]
+    fn test_market_order_sweeps_levels_and_never_rests() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 1, 100.0, 5.0, 1).unwrap();
+        book.add_order(2, 1, 101.0, 5.0, 2).unwrap();
+
+        let matches = book
+            .execute(typed(3, Side::Buy, OrderType::Market, "0", "12"))
+            .unwrap()
+            .matches;
+
+        assert_eq!(matches.len(), 2);
+        assert_eq!(
+            (matches[0].sell_order_id, matches[0].price),
+            (1, dec("100"))
+        );
+        assert_eq!(
+            (matches[1].sell_order_id, matches[1].price),
+            (2, dec("101"))
+        );
+        assert_eq!(book.ask_count(), 0);
+        assert_eq!(book.bid_count(), 0);
+    }
+
+    #[test]
+    fn test_ioc_respects_limit_and_discards_remainder() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 0, 100.0, 4.0, 1).unwrap();
+        book.add_order(2, 0, 98.0, 4.0, 2).unwrap();
+
+        let matches = book
+            .execute(typed(
+                3,
+                Side::Sell,
+                OrderType::ImmediateOrCancel,
+                "99",
+                "10",
+            ))
+            .unwrap()
+            .matches;
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].buy_order_id, 1);
+        assert_eq!(matches[0].quantity, dec("4"));
+        assert_eq!(book.best_bid_price(), Some(98.0));
+        assert_eq!(book.ask_count(), 0);
+    }
+
+    #[test]
+    fn test_fok_fills_completely_or_not_at_all() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 1, 100.0, 3.0, 1).unwrap();
+        book.add_order(2, 1, 102.0, 3.0, 2).unwrap();
+
+        let killed = book
+            .execute(typed(3, Side::Buy, OrderType::FillOrKill, "101", "5"))
+            .unwrap();
+        assert!(killed.matches.is_empty());
+        assert_eq!(book.ask_count(), 2);
+
+        let filled = book
+            .execute(typed(4, Side::Buy, OrderType::FillOrKill, "102", "5"))
+            .unwrap();
+        let total: Decimal = filled.matches.iter().map(|m| m.quantity).sum();
+        assert_eq!(total, dec("5"));
+        assert_eq!(book.ask_count(), 1);
+        assert_eq!(book.bid_count(), 0);
+    }
+
+    #[test]
+    fn test_submit_limit_matches_on_arrival_and_rests_remainder() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 1, 100.0, 4.0, 1).unwrap();
+        book.add_order(2, 1, 103.0, 4.0, 2).unwrap();
+
+        let result = book.submit(order(3, Side::Buy, "101", "10")).unwrap();
+
+        assert_eq!(result.matches.len(), 1);
+        assert_eq!(result.matches[0].price, dec("100"));
+        let resting = result.resting.expect("limit remainder should rest");
+        assert_eq!(resting.quantity, dec("6"));
+        assert_eq!(book.best_bid_price(), Some(101.0));
+        assert_eq!(book.best_ask_price(), Some(103.0));
+    }
+
+    #[test]
+    fn test_match_orders_prices_at_maker_by_arrival() {
+        let mut book = OrderBook::new();
+        // Timestamps disagree with arrival order; the first order to rest is the maker
+        book.add_order(1, 1, 99.0, 5.0, 50).unwrap();
+        book.add_order(2, 0, 101.0, 5.0, 10).unwrap();
+
+        let matches = book.match_crossed().matches;
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].price, dec("99"));
+        assert_eq!(book.bid_count() + book.ask_count(), 0);
+    }
+
+    #[test]
+    fn test_amend_reduce_keeps_queue_priority() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 1, 100.0, 5.0, 1).unwrap();
+        book.add_order(2, 1, 100.0, 5.0, 2).unwrap();
+
+        assert_eq!(book.amend(1, 100.0, 3.0), Ok(true));
+        let matches = book.take(&mut order(3, Side::Buy, "100", "3")).matches;
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].sell_order_id, 1);
+        assert_eq!(matches[0].quantity, dec("3"));
+    }
+
+    #[test]
+    fn test_amend_increase_or_reprice_loses_priority() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 1, 100.0, 5.0, 1).unwrap();
+        book.add_order(2, 1, 100.0, 5.0, 2).unwrap();
+        book.add_order(3, 1, 101.0, 5.0, 3).unwrap();
+
+        assert_eq!(book.amend(1, 100.0, 6.0), Ok(true));
+        assert_eq!(book.amend(3, 100.0, 5.0), Ok(true));
+        assert_eq!(book.amend(999, 100.0, 1.0), Ok(false));
+        assert_eq!(
+            book.amend(2, 100.0, 0.0),
+            Err(RejectReason::InvalidQuantity)
+        );
+
+        let matches = book.take(&mut order(4, Side::Buy, "100", "16")).matches;
+        let fill_order: Vec<u32> = matches.iter().map(|m| m.sell_order_id).collect();
+        assert_eq!(fill_order, vec![2, 1, 3]);
+        assert_eq!(book.ask_count(), 0);
+    }
+
+    #[test]
+    fn test_negative_prices_order_correctly() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 0, -12.5, 1.0, 1).unwrap();
+        book.add_order(2, 0, -3.0, 1.0, 2).unwrap();
+        book.add_order(3, 1, -1.0, 1.0, 3).unwrap();
+
+        assert_eq!(book.best_bid_price(), Some(-3.0));
+        assert_eq!(book.best_ask_price(), Some(-1.0));
+        assert_eq!(book.spread(), Some(2.0));
+    }
+
+    #[test]
+    fn test_fills_conserve_quantity_exactly() {
+        let mut book = OrderBook::with_precision(2, 3).unwrap();
+        for (id, qty) in [(1, 0.1), (2, 0.2), (3, 0.3)] {
+            book.add_order(id, 1, 50.0, qty, id as u64).unwrap();
+        }
+
+        let mut taker = book
+            .order_from_f64(4, Side::Buy, 50.0, 0.45, 4)
+            .expect("valid order");
+        let matches = book.take(&mut taker).matches;
+
+        let filled: Decimal = matches.iter().map(|m| m.quantity).sum();
+        assert_eq!(filled, dec("0.45"));
+        assert!(taker.quantity.is_zero());
+        let resting: Decimal = book.asks.values().flatten().map(|o| o.quantity).sum();
+        assert_eq!(resting, dec("0.15"));
+    }
+
+    fn tick_lot(tick: &str, lot: &str) -> InstrumentRules {
+        InstrumentRules {
+            tick_size: Some(dec(tick)),
+            lot_size: Some(dec(lot)),
+            ..InstrumentRules::default()
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_values_and_duplicates() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 0, 100.0, 1.0, 1).unwrap();
+
+        let nan_price = book.order_from_f64(2, Side::Buy, f64::NAN, 1.0, 2);
+        assert_eq!(nan_price.unwrap_err(), RejectReason::InvalidPrice);
+        let zero_qty = book.order_from_f64(2, Side::Buy, 100.0, 0.0, 2);
+        assert_eq!(zero_qty.unwrap_err(), RejectReason::InvalidQuantity);
+        // Values past MAX_ORDER_UNITS are refused before they reach any sum
+        let huge_qty = book.order_from_f64(2, Side::Buy, 100.0, 1e13, 2);
+        assert_eq!(huge_qty.unwrap_err(), RejectReason::InvalidQuantity);
+        let huge_price = book.order_from_f64(2, Side::Buy, -1e13, 1.0, 2);
+        assert_eq!(huge_price.unwrap_err(), RejectReason::InvalidPrice);
+        let duplicate = book.submit(order(1, Side::Buy, "99", "1"));
+        assert_eq!(duplicate.unwrap_err(), RejectReason::DuplicateOrderId);
+        assert_eq!(book.bid_count(), 1);
+    }
+
+    #[test]
+    fn test_tick_lot_and_notional_rules() {
+        let mut book = OrderBook::new();
+        let mut rules = tick_lot("0.05", "0.5");
+        rules.min_notional = Some(dec("10"));
+        rules.max_notional = Some(dec("1000"));
+        book.set_instrument_rules(rules).unwrap();
+
+        assert_eq!(
+            book.submit(order(1, Side::Buy, "100.02", "1")).unwrap_err(),
+            RejectReason::TickSize {
+                tick_size: dec("0.05")
+            }
+        );
+        assert_eq!(
+            book.submit(order(2, Side::Buy, "100", "1.2")).unwrap_err(),
+            RejectReason::LotSize {
+                lot_size: dec("0.5")
+            }
+        );
+        assert_eq!(
+            book.submit(order(3, Side::Buy, "-5", "1.5")).unwrap_err(),
+            RejectReason::BelowMinNotional {
+                notional: dec("7.5"),
+                min_notional: dec("10"),
+            }
+        );
+        assert!(matches!(
+            book.submit(order(4, Side::Sell, "100", "10.5")),
+            Err(RejectReason::AboveMaxNotional { .. })
+        ));
+        assert!(book.submit(order(5, Side::Buy, "100.05", "2.5")).is_ok());
+        assert!(book
+            .set_instrument_rules(tick_lot("0.0000001", "1"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_price_band_follows_last_trade() {
+        let mut book = OrderBook::new();
+        book.set_instrument_rules(InstrumentRules {
+            price_band: Some(dec("5")),
+            ..InstrumentRules::default()
+        })
+        .unwrap();
+
+        // No trade yet, so any price is accepted
+        book.add_order(1, 1, 200.0, 1.0, 1).unwrap();
+        book.add_order(2, 0, 200.0, 1.0, 2).unwrap();
+        book.match_crossed();
+
+        assert_eq!(
+            book.submit(order(3, Side::Buy, "194.99", "1")).unwrap_err(),
+            RejectReason::OutsidePriceBand {
+                low: dec("195"),
+                high: dec("205"),
+            }
+        );
+        assert!(book.submit(order(4, Side::Buy, "195", "1")).is_ok());
+        let market = typed(5, Side::Buy, OrderType::Market, "0", "1");
+        assert!(book.submit(market).is_ok());
+    }
+
+    fn owned(id: u32, side: Side, price: &str, quantity: &str, owner: u32) -> Order {
+        Order {
+            owner: Some(owner),
+            ..order(id, side, price, quantity)
+        }
+    }
+
+    fn stp(order_id: u32, quantity: &str) -> StpCancel {
+        StpCancel {
+            order_id,
+            quantity: dec(quantity),
+        }
+    }
+
+    #[test]
+    fn test_stp_cancel_newest_skips_own_resting_order() {
+        let mut book = OrderBook::new();
+        book.submit(owned(1, Side::Sell, "100", "5", 7)).unwrap();
+        book.submit(owned(2, Side::Sell, "101", "5", 8)).unwrap();
+
+        let result = book.submit(owned(3, Side::Buy, "101", "5", 7)).unwrap();
+
+        assert!(result.matches.is_empty());
+        assert_eq!(result.stp_cancelled, vec![stp(3, "5")]);
+        assert!(result.resting.is_none());
+        assert_eq!(book.ask_count(), 2);
+    }
+
+    #[test]
+    fn test_stp_cancel_oldest_removes_own_order_and_continues() {
+        let mut book = OrderBook::new();
+        book.set_self_trade_prevention(1);
+        book.submit(owned(1, Side::Sell, "100", "5", 7)).unwrap();
+        book.submit(owned(2, Side::Sell, "101", "5", 8)).unwrap();
+
+        let result = book.submit(owned(3, Side::Buy, "101", "4", 7)).unwrap();
+
+        assert_eq!(result.stp_cancelled, vec![stp(1, "5")]);
+        assert_eq!(result.matches.len(), 1);
+        assert_eq!(
+            (result.matches[0].sell_order_id, result.matches[0].price),
+            (2, dec("101"))
+        );
+        assert!(!book.cancel_order(1));
+        assert_eq!(book.ask_count(), 1);
+    }
+
+    #[test]
+    fn test_stp_cancel_both_and_decrement_in_crossed_book() {
+        let mut book = OrderBook::new();
+        book.set_self_trade_prevention(2);
+        let o = book.normalize(owned(1, Side::Sell, "100", "5", 7)).unwrap();
+        book.rest(o);
+        let o = book.normalize(owned(2, Side::Buy, "100", "3", 7)).unwrap();
+        book.rest(o);
+        let report = book.match_crossed();
+        assert!(report.matches.is_empty());
+        assert_eq!(report.stp_cancelled, vec![stp(2, "3"), stp(1, "5")]);
+        assert_eq!((book.bid_count(), book.ask_count()), (0, 0));
+
+        book.set_self_trade_prevention(3);
+        let o = book.normalize(owned(3, Side::Sell, "100", "5", 7)).unwrap();
+        book.rest(o);
+        let o = book.normalize(owned(4, Side::Buy, "100", "3", 7)).unwrap();
+        book.rest(o);
+        let report = book.match_crossed();
+        assert_eq!(report.stp_cancelled, vec![stp(4, "3"), stp(3, "3")]);
+        assert_eq!(book.bid_count(), 0);
+        assert_eq!(book.best_ask(), Some(dec("100")));
+        assert_eq!(book.asks.values().next().unwrap()[0].quantity, dec("2"));
+    }
+
+    #[test]
+    fn test_stp_cancels_iceberg_reserve() {
+        let mut book = OrderBook::new();
+        book.set_self_trade_prevention(1);
+        let own_iceberg = Order {
+            owner: Some(7),
+            ..iceberg(1, Side::Sell, "100", "10", "2")
+        };
+        book.submit(own_iceberg).unwrap();
+        book.submit(owned(2, Side::Sell, "100", "3", 8)).unwrap();
+
+        // The whole iceberg goes once; it does not reload behind order 2
+        let result = book.submit(owned(3, Side::Buy, "100", "5", 7)).unwrap();
+        assert_eq!(result.stp_cancelled, vec![stp(1, "10")]);
+        assert_eq!(result.matches.len(), 1);
+        assert_eq!(result.matches[0].sell_order_id, 2);
+        assert_eq!(result.resting.unwrap().quantity, dec("2"));
+        assert_eq!(book.ask_count(), 0);
+
+        // A newer iceberg crossing an own order is cancelled in full
+        book.set_self_trade_prevention(0);
+        let o = book.normalize(owned(4, Side::Sell, "99", "5", 9)).unwrap();
+        book.rest(o);
+        let newer = Order {
+            owner: Some(9),
+            ..iceberg(5, Side::Buy, "99", "10", "2")
+        };
+        let o = book.normalize(newer).unwrap();
+        book.rest(o);
+        let report = book.match_crossed();
+        assert_eq!(report.stp_cancelled, vec![stp(5, "10")]);
+        assert!(!book.cancel_order(5));
+        assert_eq!(book.ask_count(), 1);
+    }
+
+    #[test]
+    fn test_stp_fok_does_not_count_own_liquidity() {
+        let mut book = OrderBook::new();
+        book.submit(owned(1, Side::Sell, "100", "5", 7)).unwrap();
+        book.submit(owned(2, Side::Sell, "100", "5", 8)).unwrap();
+
+        let fok = Order {
+            order_type: OrderType::FillOrKill,
+            ..owned(3, Side::Buy, "100", "8", 7)
+        };
+        let result = book.submit(fok).unwrap();
+        assert!(result.matches.is_empty());
+        assert!(result.stp_cancelled.is_empty());
+        assert_eq!(book.ask_count(), 2);
+    }
+
+    #[test]
+    fn test_pro_rata_allocation_shares_a_level_by_size() {
+        let mut book = OrderBook::new();
+        book.allocation = AllocationPolicy {
+            algorithm: AllocationAlgorithm::ProRata,
+            ..AllocationPolicy::default()
+        };
+        book.set_instrument_rules(tick_lot("0.01", "1")).unwrap();
+        book.add_order(1, 1, 100.0, 10.0, 1).unwrap();
+        book.add_order(2, 1, 100.0, 30.0, 2).unwrap();
+        book.add_order(3, 1, 101.0, 10.0, 3).unwrap();
+
+        let result = book.submit(order(4, Side::Buy, "100", "20")).unwrap();
+
+        let fills: Vec<(u32, Decimal)> = result
+            .matches
+            .iter()
+            .map(|m| (m.sell_order_id, m.quantity))
+            .collect();
+        assert_eq!(fills, vec![(1, dec("5")), (2, dec("15"))]);
+        assert!(result.resting.is_none());
+        assert_eq!(book.ask_count(), 3);
+    }
+
+    #[test]
+    fn test_crossed_book_shares_only_among_older_orders() {
+        let mut book = OrderBook::new();
+        book.allocation = AllocationPolicy {
+            algorithm: AllocationAlgorithm::ProRata,
+            ..AllocationPolicy::default()
+        };
+        book.add_order(1, 1, 100.0, 10.0, 1).unwrap();
+        book.add_order(2, 1, 100.0, 10.0, 2).unwrap();
+        book.add_order(3, 0, 100.0, 10.0, 3).unwrap();
+        book.add_order(4, 1, 100.0, 10.0, 4).unwrap();
+
+        let report = book.match_crossed();
+
+        // Order 4 arrived after the bid, so it does not share in the fill
+        let fills: Vec<(u32, Decimal)> = report
+            .matches
+            .iter()
+            .map(|m| (m.sell_order_id, m.quantity))
+            .collect();
+        assert_eq!(fills, vec![(1, dec("5")), (2, dec("5"))]);
+        assert_eq!(book.bid_count(), 0);
+        assert_eq!(book.ask_count(), 3);
+    }
+
+    #[test]
+    fn test_depth_returns_requested_levels_with_cumulative_quantity() {
+        let mut book = OrderBook::new();
+        for (id, price) in [(1, 100.0), (2, 100.0), (3, 99.0), (4, 98.0)] {
+            book.add_order(id, 0, price, 2.0, id as u64).unwrap();
+        }
+        book.add_order(5, 1, 101.0, 1.5, 5).unwrap();
+
+        let depth = book.depth(2);
+
+        assert_eq!(depth.bids, vec![(100.0, 4.0), (99.0, 6.0)]);
+        assert_eq!(depth.asks, vec![(101.0, 1.5)]);
+        assert!(book.depth(0).bids.is_empty());
+    }
+
+    #[test]
+    fn test_market_data_reports_order_and_level_changes() {
+        let mut book = OrderBook::new();
+        book.set_market_data(true);
+        book.add_order(1, 1, 100.0, 5.0, 1).unwrap();
+        book.add_order(2, 1, 100.0, 5.0, 2).unwrap();
+        book.submit(order(3, Side::Buy, "100", "7")).unwrap();
+        assert!(!book.cancel_order(42));
+
+        let updates = std::mem::take(&mut book.updates);
+        let sequences: Vec<u64> = updates.iter().map(|u| u.sequence).collect();
+        assert_eq!(sequences, vec![1, 2, 3]);
+
+        let fill = &updates[2];
+        assert_eq!(
+            fill.orders,
+            vec![
+                OrderEvent::Delete {
+                    order_id: 1,
+                    side: Side::Sell,
+                    price: dec("100"),
+                },
+                OrderEvent::Modify {
+                    order_id: 2,
+                    side: Side::Sell,
+                    price: dec("100"),
+                    quantity: dec("3"),
+                },
+            ]
+        );
+        assert_eq!(
+            fill.levels,
+            vec![LevelUpdate {
+                side: Side::Sell,
+                price: dec("100"),
+                quantity: dec("3"),
+                order_count: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_replica_rebuilds_from_snapshot_and_updates() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 0, 99.0, 5.0, 1).unwrap();
+        book.add_order(2, 1, 101.0, 5.0, 2).unwrap();
+        book.set_market_data(true);
+        let mut replica = OrderBook::restore(book.snapshot()).unwrap();
+
+        book.add_order(3, 0, 99.0, 2.0, 3).unwrap();
+        book.add_order(4, 1, 98.0, 6.0, 4).unwrap();
+        book.amend(3, 100.0, 2.0).unwrap();
+        book.match_crossed();
+        book.cancel_order(2);
+
+        for update in std::mem::take(&mut book.updates) {
+            replica.apply_update(&update).unwrap();
+        }
+        let (source, copy) = (book.snapshot(), replica.snapshot());
+        assert_eq!(copy.sequence, source.sequence);
+        assert_eq!(copy.bids, source.bids);
+        assert_eq!(copy.asks, source.asks);
+        assert_eq!(replica.best_bid(), Some(dec("99")));
+        assert_eq!(replica.best_ask(), None);
+
+        let stale = MarketDataUpdate {
+            sequence: source.sequence,
+            orders: Vec::new(),
+            levels: Vec::new(),
+            indicative: None,
+        };
+        assert!(replica.apply_update(&stale).is_ok());
+        let gap = MarketDataUpdate {
+            sequence: source.sequence + 2,
+            ..stale
+        };
+        assert!(replica.apply_update(&gap).is_err());
+    }
+
+    #[test]
+    fn test_fills_feed_trade_history() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 1, 100.0, 2.0, 1_000).unwrap();
+        book.add_order(2, 1, 101.0, 2.0, 2_000).unwrap();
+        let taker = Order::new(3, Side::Buy, dec("101"), dec("3"), 90_000);
+        book.submit(taker).unwrap();
+
+        let tape = book.trades.recent(10);
+        assert_eq!(tape.len(), 2);
+        assert!(tape.iter().all(|t| t.timestamp == 90_000));
+        assert_eq!(book.trades.last_price(), Some(dec("101")));
+
+        let stats = book.trades.stats(90_000, book.price_scale);
+        assert_eq!(stats.volume_24h, dec("3"));
+        assert_eq!(stats.vwap_24h, Some(dec("100.333333")));
+
+        let candles = book.trades.candles(60_000).unwrap();
+        assert_eq!(candles.len(), 1);
+        assert_eq!((candles[0].start, candles[0].high), (60_000, dec("101")));
+    }
+
+    fn stop(
+        id: u32,
+        side: Side,
+        order_type: OrderType,
+        stop: &str,
+        price: &str,
+        qty: &str,
+    ) -> Order {
+        Order {
+            stop_price: Some(dec(stop)),
+            ..typed(id, side, order_type, price, qty)
+        }
+    }
+
+    #[test]
+    fn test_stops_wait_for_trigger_then_match_in_same_call() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 0, 99.0, 5.0, 1).unwrap();
+        book.add_order(2, 0, 98.0, 5.0, 2).unwrap();
+        book.add_order(3, 1, 101.0, 5.0, 3).unwrap();
+
+        // Sell stop at 99 waits: no trade yet
+        let parked = book
+            .submit(stop(4, Side::Sell, OrderType::Market, "99", "0", "4"))
+            .unwrap();
+        assert!(parked.matches.is_empty() && parked.triggered_stops.is_empty());
+        assert_eq!(book.stop_count(), 1);
+        // Buy stop-limit far above the market also waits
+        book.submit(stop(5, Side::Buy, OrderType::Limit, "105", "106", "1"))
+            .unwrap();
+
+        // A crossed sell at 99 trades, which fires the sell stop
+        book.add_order(6, 1, 99.0, 2.0, 6).unwrap();
+        let report = book.match_crossed();
+
+        assert_eq!(report.triggered_stops, vec![4]);
+        let fills: Vec<(u32, u32, Decimal)> = report
+            .matches
+            .iter()
+            .map(|m| (m.buy_order_id, m.sell_order_id, m.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![(1, 6, dec("2")), (1, 4, dec("3")), (2, 4, dec("1"))]
+        );
+        assert_eq!(book.stop_count(), 1);
+        assert_eq!(book.best_bid(), Some(dec("98")));
+    }
+
+    #[test]
+    fn test_amend_parked_stop() {
+        let mut book = OrderBook::new();
+        book.add_order(10, 1, 106.0, 10.0, 1).unwrap();
+        book.submit(stop(1, Side::Buy, OrderType::Limit, "105", "106", "2"))
+            .unwrap();
+        book.submit(stop(2, Side::Buy, OrderType::Limit, "105", "106", "2"))
+            .unwrap();
+
+        // Growing stop 1 moves it behind stop 2; shrinking stop 2 does not
+        assert_eq!(book.amend(1, 106.0, 3.0), Ok(true));
+        assert_eq!(book.amend(2, 106.0, 1.0), Ok(true));
+        assert_eq!(
+            book.amend(2, 106.0, 0.0),
+            Err(RejectReason::InvalidQuantity)
+        );
+        assert_eq!(book.stop_count(), 2);
+        assert_eq!(book.stops.get(1).unwrap().stop_price, Some(dec("105")));
+
+        book.add_order(11, 1, 105.0, 1.0, 2).unwrap();
+        book.add_order(12, 0, 105.0, 1.0, 3).unwrap();
+        let report = book.match_crossed();
+
+        assert_eq!(report.triggered_stops, vec![2, 1]);
+        let fills: Vec<(u32, u32, Decimal)> = report
+            .matches
+            .iter()
+            .map(|m| (m.buy_order_id, m.sell_order_id, m.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![(12, 11, dec("1")), (2, 10, dec("1")), (1, 10, dec("3"))]
+        );
+        assert_eq!(book.stop_count(), 0);
+    }
+
+    #[test]
+    fn test_stop_cascade_and_immediate_trigger() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 1, 100.0, 1.0, 1).unwrap();
+        book.add_order(2, 1, 102.0, 1.0, 2).unwrap();
+        book.add_order(3, 1, 104.0, 1.0, 3).unwrap();
+        book.submit(stop(4, Side::Buy, OrderType::Market, "100", "0", "1"))
+            .unwrap();
+        book.submit(stop(5, Side::Buy, OrderType::Market, "102", "0", "1"))
+            .unwrap();
+
+        // Trade at 100 fires stop 4, whose fill at 102 fires stop 5
+        let result = book.submit(order(6, Side::Buy, "100", "1")).unwrap();
+        assert_eq!(result.triggered_stops, vec![4, 5]);
+        assert_eq!(result.matches.len(), 3);
+        assert_eq!(book.trades.last_price(), Some(dec("104")));
+        assert_eq!(book.stop_count(), 0);
+
+        // Last trade is already above this buy stop, so it enters at once
+        book.add_order(7, 1, 105.0, 1.0, 7).unwrap();
+        let result = book
+            .submit(stop(8, Side::Buy, OrderType::Limit, "103", "105", "1"))
+            .unwrap();
+        assert_eq!(result.triggered_stops, vec![8]);
+        assert_eq!(result.matches[0].sell_order_id, 7);
+
+        // Parked stops can be cancelled and share the id space
+        book.submit(stop(9, Side::Sell, OrderType::Market, "90", "0", "1"))
+            .unwrap();
+        assert_eq!(
+            book.submit(order(9, Side::Buy, "1", "1")).unwrap_err(),
+            RejectReason::DuplicateOrderId
+        );
+        assert!(book.cancel_order(9));
+        assert_eq!(book.stop_count(), 0);
+    }
+
+    fn iceberg(id: u32, side: Side, price: &str, qty: &str, peak: &str) -> Order {
+        Order {
+            display_quantity: Some(dec(peak)),
+            ..order(id, side, price, qty)
+        }
+    }
+
+    #[test]
+    fn test_iceberg_shows_peak_and_requeues_on_refresh() {
+        let mut book = OrderBook::new();
+        let rested = book
+            .submit(iceberg(1, Side::Sell, "100", "10", "2"))
+            .unwrap()
+            .resting
+            .unwrap();
+        assert_eq!(
+            (rested.quantity, rested.hidden_quantity),
+            (dec("2"), dec("8"))
+        );
+        book.add_order(2, 1, 100.0, 2.0, 2).unwrap();
+        assert_eq!(book.depth(1).asks, vec![(100.0, 4.0)]);
+
+        // The displayed slice fills first, then the reload goes behind order 2
+        let fills = book
+            .submit(order(3, Side::Buy, "100", "3"))
+            .unwrap()
+            .matches;
+        let fills: Vec<(u32, Decimal)> = fills
+            .iter()
+            .map(|m| (m.sell_order_id, m.quantity))
+            .collect();
+        assert_eq!(fills, vec![(1, dec("2")), (2, dec("1"))]);
+        let level = book.level(Side::Sell, dec("100.000000").units()).unwrap();
+        assert_eq!(
+            level.iter().map(|o| (o.id, o.quantity)).collect::<Vec<_>>(),
+            vec![(2, dec("1")), (1, dec("2"))]
+        );
+
+        // A large taker consumes the reserve through repeated refreshes
+        let fills = book.submit(order(4, Side::Buy, "100", "20")).unwrap();
+        let total: Decimal = fills.matches.iter().map(|m| m.quantity).sum();
+        assert_eq!(total, dec("9"));
+        assert_eq!(book.ask_count(), 0);
+        assert_eq!(fills.resting.unwrap().quantity, dec("11"));
+    }
+
+    #[test]
+    fn test_iceberg_amend_fok_and_public_feed() {
+        let mut book = OrderBook::new();
+        book.set_market_data(true);
+        book.submit(iceberg(1, Side::Sell, "100", "10", "2"))
+            .unwrap();
+        book.add_order(2, 1, 100.0, 1.0, 2).unwrap();
+
+        // Hidden size counts toward fill-or-kill
+        let fok = typed(3, Side::Buy, OrderType::FillOrKill, "100", "11");
+        let filled = book.submit(fok).unwrap().matches;
+        let total: Decimal = filled.iter().map(|m| m.quantity).sum();
+        assert_eq!(total, dec("11"));
+        assert_eq!(book.ask_count(), 0);
+
+        book.submit(iceberg(4, Side::Sell, "101", "10", "2"))
+            .unwrap();
+        book.add_order(5, 1, 101.0, 1.0, 5).unwrap();
+        // Shrinking the total takes from the reserve and keeps priority
+        assert_eq!(book.amend(4, 101.0, 3.0), Ok(true));
+        let front = book.level(Side::Sell, dec("101.000000").units()).unwrap()[0];
+        assert_eq!(
+            (front.id, front.quantity, front.hidden_quantity),
+            (4, dec("2"), dec("1"))
+        );
+
+        let leaked = book.updates.iter().flat_map(|u| &u.orders).any(|e| {
+            matches!(e, OrderEvent::Add { order } if order.hidden_quantity.is_positive()
+                || order.display_quantity.is_some())
+        });
+        assert!(!leaked);
+        assert!(book
+            .snapshot()
+            .asks
+            .iter()
+            .all(|o| o.hidden_quantity.is_zero()));
+    }
+
+    fn expiring(id: u32, side: Side, price: &str, qty: &str, expires_at: u64) -> Order {
+        Order {
+            time_in_force: TimeInForce::GoodTillTime,
+            expires_at: Some(expires_at),
+            ..order(id, side, price, qty)
+        }
+    }
+
+    #[test]
+    fn test_expired_orders_are_purged_before_matching() {
+        let mut book = OrderBook::new();
+        book.submit(expiring(1, Side::Sell, "100", "5", 50))
+            .unwrap();
+        book.submit(order(2, Side::Sell, "101", "5")).unwrap();
+
+        // Arriving after order 1 expired, the taker trades at 101 instead
+        let result = book.submit(order(60, Side::Buy, "101", "5")).unwrap();
+        assert_eq!(result.expired, vec![1]);
+        assert_eq!(result.matches.len(), 1);
+        assert_eq!(result.matches[0].sell_order_id, 2);
+        assert!(book.order_index.is_empty() && book.expiries.is_empty());
+
+        // Expiry is checked against the book clock, not just the order's time
+        assert_eq!(
+            book.submit(expiring(3, Side::Buy, "99", "1", 60)).err(),
+            Some(RejectReason::Expired { expires_at: 60 })
+        );
+        let gtt = Order {
+            time_in_force: TimeInForce::GoodTillTime,
+            ..order(4, Side::Buy, "99", "1")
+        };
+        assert_eq!(book.submit(gtt).err(), Some(RejectReason::MissingExpiry));
+    }
+
+    #[test]
+    fn test_every_entry_path_purges_expired_orders_first() {
+        let mut book = OrderBook::new();
+        book.submit(expiring(1, Side::Sell, "100", "5", 50))
+            .unwrap();
+        book.submit(expiring(2, Side::Sell, "100", "5", 70))
+            .unwrap();
+        book.submit(order(3, Side::Sell, "101", "5")).unwrap();
+
+        // A resting limit arriving after order 1 expired leaves it purged
+        // rather than resting crossed against it
+        let bid = Order {
+            timestamp: 60,
+            ..order(4, Side::Buy, "99", "5")
+        };
+        assert_eq!(book.execute(bid).unwrap().expired, vec![1]);
+        assert_eq!(book.ask_count(), 2);
+
+        // A taker arriving after order 2 expired trades at 101 instead
+        let taker = Order {
+            timestamp: 80,
+            ..typed(5, Side::Buy, OrderType::ImmediateOrCancel, "101", "5")
+        };
+        let report = book.execute(taker).unwrap();
+        assert_eq!(report.expired, vec![2]);
+        assert_eq!(report.matches.len(), 1);
+        assert_eq!(report.matches[0].sell_order_id, 3);
+        assert!(book.expiries.is_empty());
+    }
+
+    #[test]
+    fn test_expire_orders_covers_stops_and_delivery_period() {
+        let mut book = OrderBook::new();
+        let gfdp = Order {
+            time_in_force: TimeInForce::GoodForDeliveryPeriod,
+            ..order(1, Side::Buy, "99", "1")
+        };
+        assert_eq!(book.submit(gfdp).err(), Some(RejectReason::MissingExpiry));
+
+        book.set_gate_closure(1_000);
+        let rested = book.submit(gfdp).unwrap().resting.unwrap();
+        assert_eq!(rested.expires_at, Some(1_000));
+        let stop = Order {
+            time_in_force: TimeInForce::GoodTillTime,
+            expires_at: Some(500),
+            ..stop(2, Side::Sell, OrderType::Market, "90", "0", "1")
+        };
+        book.submit(stop).unwrap();
+        // A filled order leaves a stale entry that must not purge a reused id
+        book.submit(expiring(3, Side::Sell, "105", "1", 500))
+            .unwrap();
+        book.submit(order(4, Side::Buy, "105", "1")).unwrap();
+        book.submit(order(3, Side::Sell, "106", "1")).unwrap();
+
+        let purged: Vec<u32> = book.expire(500).iter().map(|o| o.id).collect();
+        assert_eq!(purged, vec![2]);
+        assert_eq!(book.stop_count(), 0);
+        let purged: Vec<u32> = book.expire(1_000).iter().map(|o| o.id).collect();
+        assert_eq!(purged, vec![1]);
+        assert_eq!((book.bid_count(), book.ask_count()), (0, 1));
+    }
 }
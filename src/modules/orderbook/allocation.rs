@@ -0,0 +1,175 @@
+//! Allocation Policies
+//!
+//! Rules for sharing an incoming fill among the resting orders at a single
+//! price level. Price priority across levels is unaffected; these only decide
+//! who trades within a level.
+
+use super::super::decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum AllocationAlgorithm {
+    /// Strict time priority within the level
+    #[default]
+    Fifo = 0,
+    /// Shares proportional to resting size
+    ProRata = 1,
+    /// `fifo_percent` of each fill in time priority, the rest pro rata
+    SizeTime = 2,
+}
+
+/// Per-book allocation settings
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AllocationPolicy {
+    #[serde(default)]
+    pub algorithm: AllocationAlgorithm,
+    /// Fill the oldest order at the level in full before sharing the rest
+    #[serde(default)]
+    pub top_order_priority: bool,
+    /// Share of each fill allocated in time priority under `SizeTime` (0-100)
+    #[serde(default)]
+    pub fifo_percent: u8,
+}
+
+impl AllocationPolicy {
+    pub(super) fn validate(&self) -> Result<(), String> {
+        if self.fifo_percent > 100 {
+            return Err("fifo_percent must be between 0 and 100".to_string());
+        }
+        Ok(())
+    }
+
+    /// Split `quantity` among `resting` quantities given in queue order.
+    /// Pro-rata shares are rounded down to whole `lot`s and the rounding
+    /// remainder goes to the earliest orders with size left.
+    pub(super) fn allocate(
+        &self,
+        quantity: Decimal,
+        resting: &[Decimal],
+        lot: Decimal,
+    ) -> Vec<Decimal> {
+        let scale = lot.scale();
+        let units = |d: Decimal| d.round_to(scale).map_or(0, Decimal::units) as i128;
+        let lot = units(lot).max(1);
+        let capacity: Vec<i128> = resting.iter().map(|&q| units(q)).collect();
+        let mut filled = vec![0; resting.len()];
+        let mut remaining = units(quantity).min(capacity.iter().sum());
+
+        if self.top_order_priority && !resting.is_empty() {
+            remaining -= fill_in_order(&capacity[..1], &mut filled[..1], remaining);
+        }
+        match self.algorithm {
+            AllocationAlgorithm::Fifo => {
+                fill_in_order(&capacity, &mut filled, remaining);
+            }
+            AllocationAlgorithm::ProRata => {
+                pro_rata(&capacity, &mut filled, remaining, lot);
+            }
+            AllocationAlgorithm::SizeTime => {
+                let fifo_part = remaining * self.fifo_percent as i128 / 100 / lot * lot;
+                remaining -= fill_in_order(&capacity, &mut filled, fifo_part);
+                pro_rata(&capacity, &mut filled, remaining, lot);
+            }
+        }
+
+        filled
+            .into_iter()
+            .map(|u| Decimal::new(u as i64, scale))
+            .collect()
+    }
+}
+
+/// Top up orders in queue order, returning the amount placed
+fn fill_in_order(capacity: &[i128], filled: &mut [i128], amount: i128) -> i128 {
+    let mut left = amount;
+    for (cap, fill) in capacity.iter().zip(filled.iter_mut()) {
+        let take = left.min(cap - *fill);
+        *fill += take;
+        left -= take;
+    }
+    amount - left
+}
+
+/// Share `amount` in proportion to each order's open size, in whole lots
+fn pro_rata(capacity: &[i128], filled: &mut [i128], amount: i128, lot: i128) {
+    let open: Vec<i128> = capacity
+        .iter()
+        .zip(filled.iter())
+        .map(|(c, f)| c - f)
+        .collect();
+    let total: i128 = open.iter().sum();
+    if total == 0 || amount <= 0 {
+        return;
+    }
+    let mut placed = 0;
+    for (size, fill) in open.iter().zip(filled.iter_mut()) {
+        let share = amount * size / total / lot * lot;
+        *fill += share;
+        placed += share;
+    }
+    fill_in_order(capacity, filled, amount - placed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn decs(values: &[&str]) -> Vec<Decimal> {
+        values.iter().map(|s| dec(s)).collect()
+    }
+
+    #[test]
+    fn test_pro_rata_rounds_to_lots_and_gives_remainder_in_queue_order() {
+        let policy = AllocationPolicy {
+            algorithm: AllocationAlgorithm::ProRata,
+            ..AllocationPolicy::default()
+        };
+        let resting = decs(&["10", "20", "30"]);
+
+        let shares = policy.allocate(dec("10"), &resting, dec("1"));
+
+        // Exact shares are 1.67 / 3.33 / 5; whole lots give 1 / 3 / 5 and the
+        // leftover lot goes to the oldest order
+        assert_eq!(shares, decs(&["2", "3", "5"]));
+    }
+
+    #[test]
+    fn test_top_order_priority_fills_first_order_before_sharing() {
+        let policy = AllocationPolicy {
+            algorithm: AllocationAlgorithm::ProRata,
+            top_order_priority: true,
+            ..AllocationPolicy::default()
+        };
+        let resting = decs(&["4", "10", "30"]);
+
+        let shares = policy.allocate(dec("24"), &resting, dec("1"));
+
+        assert_eq!(shares, decs(&["4", "5", "15"]));
+    }
+
+    #[test]
+    fn test_size_time_splits_fill_between_fifo_and_pro_rata() {
+        let policy = AllocationPolicy {
+            algorithm: AllocationAlgorithm::SizeTime,
+            fifo_percent: 40,
+            ..AllocationPolicy::default()
+        };
+        let resting = decs(&["10", "10"]);
+
+        let shares = policy.allocate(dec("10"), &resting, dec("1"));
+
+        // 4 by time priority, then the remaining 6 split over open sizes 6 and 10
+        assert_eq!(shares, decs(&["7", "3"]));
+        assert_eq!(shares.iter().copied().sum::<Decimal>(), dec("10"));
+        assert!(AllocationPolicy {
+            fifo_percent: 101,
+            ..policy
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Book Analytics
+//!
+//! Order-flow indicators for dashboards, computed in a single pass over each
+//! side of the book. Only displayed quantity counts, matching what depth and
+//! market data show.
+
+use super::super::decimal::Decimal;
+use super::{Order, OrderBook};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// Indicators over the top `levels` price levels of each side
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BookAnalytics {
+    pub levels: usize,
+    pub bid_volume: Decimal,
+    pub ask_volume: Decimal,
+    /// `(bid_volume - ask_volume) / (bid_volume + ask_volume)`, from -1 to 1
+    pub imbalance: Option<f64>,
+    pub mid_price: Option<Decimal>,
+    /// Best bid and ask weighted by the size on the opposite side
+    pub microprice: Option<Decimal>,
+    /// Band around mid, in percent, used for the liquidity figures
+    pub band_percent: f64,
+    pub bid_liquidity: Decimal,
+    pub ask_liquidity: Decimal,
+    /// Imbalance with level `i` (from 0) weighted by `(levels - i) / levels`,
+    /// so size near the touch counts most; from -1 to 1
+    pub pressure: Option<f64>,
+}
+
+// Per-side totals gathered in one walk from the best level outwards
+#[derive(Default)]
+struct SideScan {
+    top: Option<(Decimal, Decimal)>,
+    volume: Decimal,
+    weighted: f64,
+    liquidity: Decimal,
+}
+
+#[wasm_bindgen]
+impl OrderBook {
+    /// Imbalance, microprice, liquidity within `band_percent` of mid and book
+    /// pressure over the top `levels` levels
+    /// Returns `{ levels, bid_volume, ask_volume, imbalance, mid_price, microprice,
+    /// band_percent, bid_liquidity, ask_liquidity, pressure }`
+    pub fn get_analytics(&self, levels: usize, band_percent: f64) -> Result<JsValue, JsValue> {
+        if !band_percent.is_finite() || band_percent < 0.0 {
+            return Err(JsValue::from_str(
+                "band_percent must be a non-negative number",
+            ));
+        }
+        Ok(serde_wasm_bindgen::to_value(
+            &self.analytics(levels, band_percent),
+        )?)
+    }
+}
+
+impl OrderBook {
+    pub(super) fn analytics(&self, levels: usize, band_percent: f64) -> BookAnalytics {
+        let mid_price = self.mid();
+        let band = |sign: f64| {
+            mid_price.and_then(|mid| {
+                let offset = mid.abs().to_f64() * band_percent / 100.0;
+                Decimal::from_f64(mid.to_f64() + sign * offset, self.price_scale)
+            })
+        };
+        let (low, high) = (band(-1.0), band(1.0));
+
+        let bids = scan(self.bids.values(), levels, |p| low.is_some_and(|l| p >= l));
+        let asks = scan(self.asks.values(), levels, |p| high.is_some_and(|h| p <= h));
+
+        let ratio = |b: f64, a: f64| (b + a > 0.0).then(|| (b - a) / (b + a));
+        let microprice = match (bids.top, asks.top) {
+            (Some((bid, bid_qty)), Some((ask, ask_qty))) => bid
+                .mul_round(ask_qty, self.price_scale)
+                .zip(ask.mul_round(bid_qty, self.price_scale))
+                .and_then(|(b, a)| {
+                    b.checked_add(a)?
+                        .div_round(bid_qty.checked_add(ask_qty)?, self.price_scale)
+                }),
+            _ => None,
+        };
+        BookAnalytics {
+            levels,
+            bid_volume: bids.volume,
+            ask_volume: asks.volume,
+            imbalance: ratio(bids.volume.to_f64(), asks.volume.to_f64()),
+            mid_price,
+            microprice,
+            band_percent,
+            bid_liquidity: bids.liquidity,
+            ask_liquidity: asks.liquidity,
+            pressure: ratio(bids.weighted, asks.weighted),
+        }
+    }
+}
+
+/// Walk levels best first until past both the top `levels` and the band
+fn scan<'a>(
+    book_side: impl Iterator<Item = &'a Vec<Order>>,
+    levels: usize,
+    in_band: impl Fn(Decimal) -> bool,
+) -> SideScan {
+    let mut side = SideScan::default();
+    for (i, orders) in book_side.enumerate() {
+        let Some(price) = orders.first().map(|o| o.price) else {
+            continue;
+        };
+        let banded = in_band(price);
+        if i >= levels && !banded {
+            break;
+        }
+        let quantity = orders
+            .iter()
+            .fold(Decimal::ZERO, |q, o| q.saturating_add(o.quantity));
+        if i == 0 {
+            side.top = Some((price, quantity));
+        }
+        if i < levels {
+            side.volume = side.volume.saturating_add(quantity);
+            side.weighted += quantity.to_f64() * (levels - i) as f64 / levels as f64;
+        }
+        if banded {
+            side.liquidity = side.liquidity.saturating_add(quantity);
+        }
+    }
+    side
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_analytics_over_top_levels() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 0, 99.0, 3.0, 1).unwrap();
+        book.add_order(2, 0, 98.0, 3.0, 2).unwrap();
+        book.add_order(3, 0, 90.0, 10.0, 3).unwrap();
+        book.add_order(4, 1, 101.0, 1.0, 4).unwrap();
+        book.add_order(5, 1, 102.0, 1.0, 5).unwrap();
+
+        let stats = book.analytics(2, 2.0);
+        assert_eq!((stats.bid_volume, stats.ask_volume), (dec("6"), dec("2")));
+        assert_eq!(stats.imbalance, Some(0.5));
+        assert_eq!(stats.mid_price, Some(dec("100")));
+        // (99 * 1 + 101 * 3) / 4
+        assert_eq!(stats.microprice, Some(dec("100.5")));
+        // 98..=102 excludes the bid at 90
+        assert_eq!(
+            (stats.bid_liquidity, stats.ask_liquidity),
+            (dec("6"), dec("2"))
+        );
+        // Bids 3 + 1.5, asks 1 + 0.5
+        assert_eq!(stats.pressure, Some(0.5));
+
+        let wide = book.analytics(1, 20.0);
+        assert_eq!(wide.bid_liquidity, dec("16"));
+        assert_eq!(wide.bid_volume, dec("3"));
+    }
+
+    #[test]
+    fn test_one_sided_book_has_no_mid_figures() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 0, 99.0, 3.0, 1).unwrap();
+
+        let stats = book.analytics(5, 1.0);
+        assert_eq!(stats.imbalance, Some(1.0));
+        assert_eq!((stats.mid_price, stats.microprice), (None, None));
+        assert!(stats.bid_liquidity.is_zero());
+        assert_eq!(OrderBook::new().analytics(5, 1.0).imbalance, None);
+    }
+}
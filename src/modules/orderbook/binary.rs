@@ -0,0 +1,507 @@
+//! Binary Snapshots
+//!
+//! Compact, versioned encoding of a book's full state: resting orders in
+//! queue order (iceberg reserves included), parked stops, expiry settings,
+//! rules, matching policies, account limits and positions, the trading
+//! phase and the sequence counters. Restoring gives a book
+//! that behaves exactly like the original. The trade tape and candles are
+//! not included; only the last trade price is kept, since stops and the
+//! price band depend on it.
+//!
+//! Layout: the magic bytes `GTXB`, a format version byte, then fixed-width
+//! little-endian fields. Decimals are stored as `i64` units plus a scale byte.
+
+use super::super::decimal::{Decimal, MAX_SCALE};
+use super::{
+    within_bounds, AllocationAlgorithm, AllocationPolicy, InstrumentRules, Order, OrderBook,
+    OrderType, RiskLimits, SelfTradePrevention, Side, TimeInForce, TradingPhase,
+};
+use wasm_bindgen::prelude::*;
+
+const MAGIC: &[u8; 4] = b"GTXB";
+// Version 2 added account limits and positions; version 3 the trading phase
+const SNAPSHOT_VERSION: u8 = 3;
+
+#[wasm_bindgen]
+impl OrderBook {
+    /// Encode the full book state as a binary snapshot
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode()
+    }
+
+    /// Rebuild a book from bytes produced by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<OrderBook, JsValue> {
+        OrderBook::decode(bytes).map_err(|e| JsValue::from_str(&e))
+    }
+}
+
+impl OrderBook {
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(MAGIC);
+        w.u8(SNAPSHOT_VERSION);
+
+        w.u8(self.price_scale);
+        w.u8(self.quantity_scale);
+        w.u64(self.next_sequence);
+        w.u64(self.feed_sequence);
+        w.u8(self.market_data as u8);
+        w.u64(self.clock);
+        w.option(self.gate_closure, Writer::u64);
+        w.option(self.trades.last_price(), Writer::decimal);
+
+        let rules = &self.rules;
+        for limit in [
+            rules.tick_size,
+            rules.lot_size,
+            rules.min_notional,
+            rules.max_notional,
+            rules.price_band,
+        ] {
+            w.option(limit, Writer::decimal);
+        }
+        w.u8(self.stp_mode as u8);
+        w.u8(self.allocation.algorithm as u8);
+        w.u8(self.allocation.top_order_priority as u8);
+        w.u8(self.allocation.fifo_percent);
+
+        w.u32(self.risk.limits.len() as u32);
+        for (&account, limits) in &self.risk.limits {
+            w.u32(account);
+            w.option(limits.max_open_notional, Writer::decimal);
+            w.option(limits.max_position, Writer::decimal);
+        }
+        w.u32(self.risk.positions.len() as u32);
+        for (&account, &position) in &self.risk.positions {
+            w.u32(account);
+            w.decimal(position);
+        }
+        w.u8(self.phase as u8);
+
+        for orders in [
+            self.bids.values().flatten().collect::<Vec<_>>(),
+            self.asks.values().flatten().collect(),
+            self.stops.orders().collect(),
+        ] {
+            w.u32(orders.len() as u32);
+            for order in orders {
+                w.order(order);
+            }
+        }
+        w.buf
+    }
+
+    pub(super) fn decode(bytes: &[u8]) -> Result<OrderBook, String> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err("Not an order book snapshot".to_string());
+        }
+        let version = r.u8()?;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(format!("Unsupported snapshot version {}", version));
+        }
+
+        let price_scale = r.u8()?;
+        let quantity_scale = r.u8()?;
+        if price_scale > MAX_SCALE || quantity_scale > MAX_SCALE {
+            return Err(format!(
+                "Precision must be at most {} decimal places",
+                MAX_SCALE
+            ));
+        }
+        let mut book = OrderBook {
+            price_scale,
+            quantity_scale,
+            next_sequence: r.u64()?,
+            feed_sequence: r.u64()?,
+            ..OrderBook::new()
+        };
+        let market_data = r.bool()?;
+        book.clock = r.u64()?;
+        book.gate_closure = r.option(Reader::u64)?;
+        book.trades.set_last_price(r.option(Reader::decimal)?);
+
+        // Rules and limits go through the same checks as when they were set
+        book.set_instrument_rules(InstrumentRules {
+            tick_size: r.option(Reader::decimal)?,
+            lot_size: r.option(Reader::decimal)?,
+            min_notional: r.option(Reader::decimal)?,
+            max_notional: r.option(Reader::decimal)?,
+            price_band: r.option(Reader::decimal)?,
+        })?;
+        book.stp_mode = variant(r.u8()?, &STP_MODES, "self-trade prevention mode")?;
+        book.allocation = AllocationPolicy {
+            algorithm: variant(r.u8()?, &ALGORITHMS, "allocation algorithm")?,
+            top_order_priority: r.bool()?,
+            fifo_percent: r.u8()?,
+        };
+        book.allocation.validate()?;
+
+        if version >= 2 {
+            for _ in 0..r.u32()? {
+                let account = r.u32()?;
+                let limits = RiskLimits {
+                    max_open_notional: r.option(Reader::decimal)?,
+                    max_position: r.option(Reader::decimal)?,
+                };
+                book.set_limits(account, Some(limits))?;
+            }
+            for _ in 0..r.u32()? {
+                let account = r.u32()?;
+                let position = r.decimal()?;
+                if position.scale() != quantity_scale {
+                    return Err(format!(
+                        "Position of account {} is not at the book's precision",
+                        account
+                    ));
+                }
+                book.risk.positions.insert(account, position);
+            }
+        }
+        if version >= 3 {
+            book.phase = variant(r.u8()?, &PHASES, "trading phase")?;
+        }
+
+        for section in [Some(Side::Buy), Some(Side::Sell), None] {
+            let count = r.u32()?;
+            for _ in 0..count {
+                let order = r.order()?;
+                let at_scale =
+                    |value: Decimal, scale: u8| value.scale() == scale && within_bounds(value);
+                if !at_scale(order.price, price_scale)
+                    || order.stop_price.is_some_and(|s| !at_scale(s, price_scale))
+                    || !at_scale(order.quantity, quantity_scale)
+                    || order
+                        .display_quantity
+                        .is_some_and(|d| !at_scale(d, quantity_scale))
+                    || !(order.hidden_quantity.is_zero()
+                        || at_scale(order.hidden_quantity, quantity_scale))
+                {
+                    return Err(format!("Order {} is not at the book's precision", order.id));
+                }
+                // Orders that can never fill would never leave the book
+                if !order.quantity.is_positive()
+                    || order.hidden_quantity.is_negative()
+                    || order.display_quantity.is_some_and(|d| !d.is_positive())
+                {
+                    return Err(format!("Order {} has a non-positive quantity", order.id));
+                }
+                if book.order_index.contains_key(&order.id) || book.stops.contains(order.id) {
+                    return Err(format!("Duplicate order id {}", order.id));
+                }
+                match section {
+                    Some(side) if side != order.side => {
+                        return Err(format!("Order {} is on the wrong side", order.id));
+                    }
+                    Some(_) => book.insert(order),
+                    None => {
+                        if order.stop_price.is_none() {
+                            return Err(format!("Stop order {} has no stop price", order.id));
+                        }
+                        book.next_sequence = book.next_sequence.max(order.sequence + 1);
+                        book.track_expiry(&order);
+                        book.stops.insert(order);
+                        book.sync_exposure(order.id);
+                    }
+                }
+            }
+        }
+        if r.pos != bytes.len() {
+            return Err("Trailing bytes after snapshot".to_string());
+        }
+
+        book.market_data = market_data;
+        Ok(book)
+    }
+}
+
+const SIDES: [Side; 2] = [Side::Buy, Side::Sell];
+const ORDER_TYPES: [OrderType; 4] = [
+    OrderType::Limit,
+    OrderType::Market,
+    OrderType::ImmediateOrCancel,
+    OrderType::FillOrKill,
+];
+const STP_MODES: [SelfTradePrevention; 4] = [
+    SelfTradePrevention::CancelNewest,
+    SelfTradePrevention::CancelOldest,
+    SelfTradePrevention::CancelBoth,
+    SelfTradePrevention::DecrementAndCancel,
+];
+const ALGORITHMS: [AllocationAlgorithm; 3] = [
+    AllocationAlgorithm::Fifo,
+    AllocationAlgorithm::ProRata,
+    AllocationAlgorithm::SizeTime,
+];
+const PHASES: [TradingPhase; 3] = [
+    TradingPhase::Continuous,
+    TradingPhase::PreOpen,
+    TradingPhase::Uncross,
+];
+const TIME_IN_FORCE: [TimeInForce; 3] = [
+    TimeInForce::GoodTillCancel,
+    TimeInForce::GoodTillTime,
+    TimeInForce::GoodForDeliveryPeriod,
+];
+
+/// Enum from its discriminant; unlike the `From<u8>` impls, unknown values
+/// are an error rather than a default
+fn variant<T: Copy>(tag: u8, all: &[T], what: &str) -> Result<T, String> {
+    all.get(tag as usize)
+        .copied()
+        .ok_or_else(|| format!("Invalid {} {}", what, tag))
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn decimal(&mut self, v: Decimal) {
+        self.bytes(&v.units().to_le_bytes());
+        self.u8(v.scale());
+    }
+
+    fn option<T>(&mut self, v: Option<T>, write: fn(&mut Self, T)) {
+        match v {
+            Some(v) => {
+                self.u8(1);
+                write(self, v);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn order(&mut self, order: &Order) {
+        self.u32(order.id);
+        self.u8(order.side as u8);
+        self.decimal(order.price);
+        self.decimal(order.quantity);
+        self.u64(order.timestamp);
+        self.u8(order.order_type as u8);
+        self.u64(order.sequence);
+        self.option(order.owner, Writer::u32);
+        self.option(order.stop_price, Writer::decimal);
+        self.option(order.display_quantity, Writer::decimal);
+        self.decimal(order.hidden_quantity);
+        self.u8(order.time_in_force as u8);
+        self.option(order.expires_at, Writer::u64);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| "Snapshot is truncated".to_string())?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut out = [0; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(format!("Invalid flag {}", v)),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn decimal(&mut self) -> Result<Decimal, String> {
+        let units = i64::from_le_bytes(self.array()?);
+        let scale = self.u8()?;
+        if scale > MAX_SCALE {
+            return Err(format!("Invalid decimal scale {}", scale));
+        }
+        Ok(Decimal::new(units, scale))
+    }
+
+    fn option<T>(&mut self, read: fn(&mut Self) -> Result<T, String>) -> Result<Option<T>, String> {
+        if self.bool()? {
+            read(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn order(&mut self) -> Result<Order, String> {
+        Ok(Order {
+            id: self.u32()?,
+            side: variant(self.u8()?, &SIDES, "side")?,
+            price: self.decimal()?,
+            quantity: self.decimal()?,
+            timestamp: self.u64()?,
+            order_type: variant(self.u8()?, &ORDER_TYPES, "order type")?,
+            sequence: self.u64()?,
+            owner: self.option(Reader::u32)?,
+            stop_price: self.option(Reader::decimal)?,
+            display_quantity: self.option(Reader::decimal)?,
+            hidden_quantity: self.decimal()?,
+            time_in_force: variant(self.u8()?, &TIME_IN_FORCE, "time in force")?,
+            expires_at: self.option(Reader::u64)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: u32, side: Side, price: &str, quantity: &str) -> Order {
+        Order::new(
+            id,
+            side,
+            price.parse().unwrap(),
+            quantity.parse().unwrap(),
+            id as u64,
+        )
+    }
+
+    fn sample_book() -> OrderBook {
+        let mut book = OrderBook::new();
+        book.set_instrument_rules(InstrumentRules {
+            lot_size: Some("0.5".parse().unwrap()),
+            ..InstrumentRules::default()
+        })
+        .unwrap();
+        book.set_gate_closure(10_000);
+        let limits = RiskLimits {
+            max_position: Some("50".parse().unwrap()),
+            ..RiskLimits::default()
+        };
+        book.set_limits(9, Some(limits)).unwrap();
+        book.submit(order(1, Side::Sell, "101", "2")).unwrap();
+        book.submit(Order {
+            owner: Some(9),
+            ..order(2, Side::Buy, "101", "1")
+        })
+        .unwrap();
+        book.submit(Order {
+            display_quantity: Some("1".parse().unwrap()),
+            ..order(3, Side::Sell, "100", "4")
+        })
+        .unwrap();
+        book.submit(order(4, Side::Sell, "100", "2")).unwrap();
+        book.submit(Order {
+            time_in_force: TimeInForce::GoodForDeliveryPeriod,
+            ..order(5, Side::Buy, "99", "3")
+        })
+        .unwrap();
+        book.submit(Order {
+            stop_price: Some("105".parse().unwrap()),
+            ..order(6, Side::Buy, "106", "1")
+        })
+        .unwrap();
+        book
+    }
+
+    #[test]
+    fn test_round_trip_keeps_state_and_queue_order() {
+        let mut original = sample_book();
+        let bytes = original.encode();
+        let mut restored = OrderBook::decode(&bytes).unwrap();
+        assert_eq!(restored.encode(), bytes);
+        assert_eq!(restored.stop_count(), 1);
+        assert_eq!(restored.expiries, original.expiries);
+        assert_eq!(restored.trades.last_price(), Some("101".parse().unwrap()));
+
+        // Both books fill the same orders in the same order afterwards
+        let taker = order(7, Side::Buy, "101", "6");
+        let expected = original.submit(taker).unwrap();
+        let actual = restored.submit(taker).unwrap();
+        assert_eq!(actual.matches, expected.matches);
+        assert_eq!(restored.encode(), original.encode());
+    }
+
+    #[test]
+    fn test_rejects_corrupt_snapshots() {
+        let bytes = sample_book().encode();
+        assert!(OrderBook::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(OrderBook::decode(b"JSON{}").is_err());
+
+        let mut version = bytes.clone();
+        version[4] = SNAPSHOT_VERSION + 1;
+        assert!(OrderBook::decode(&version).is_err());
+
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert!(OrderBook::decode(&trailing).is_err());
+
+        // Orders that admission would never have let in
+        let price_key = "100"
+            .parse::<Decimal>()
+            .unwrap()
+            .round_to(6)
+            .unwrap()
+            .units();
+        let corrupt = |edit: fn(&mut Order)| {
+            let mut book = sample_book();
+            edit(&mut book.level_mut(Side::Sell, price_key).unwrap()[0]);
+            OrderBook::decode(&book.encode())
+        };
+        assert!(corrupt(|o| o.quantity = Decimal::ZERO).is_err());
+        assert!(corrupt(|o| o.quantity = -o.quantity).is_err());
+        assert!(corrupt(|o| o.hidden_quantity = -o.hidden_quantity).is_err());
+        assert!(corrupt(|o| o.quantity = o.quantity.round_to(2).unwrap()).is_err());
+        assert!(corrupt(|_| {}).is_ok());
+
+        // Rules, limits and positions that could not have been set
+        let corrupt_book = |edit: fn(&mut OrderBook)| {
+            let mut book = sample_book();
+            edit(&mut book);
+            OrderBook::decode(&book.encode())
+        };
+        assert!(corrupt_book(|b| b.rules.tick_size = Some(Decimal::new(0, 6))).is_err());
+        assert!(corrupt_book(|b| b.rules.lot_size = Some(Decimal::new(-1, 6))).is_err());
+        assert!(corrupt_book(|b| {
+            b.risk.limits.get_mut(&9).unwrap().max_position = Some(Decimal::new(-1, 6))
+        })
+        .is_err());
+        assert!(corrupt_book(|b| {
+            b.risk.positions.insert(9, Decimal::new(1, 2));
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,460 @@
+//! Call Auction Phases
+//!
+//! A book can leave continuous trading for a call phase. In pre-open, limit
+//! and stop-limit orders are accepted and rest without matching (matching
+//! passes do nothing), while the book
+//! keeps an indicative uncrossing price and volume from the same price rule
+//! as `AuctionSimulator`. The uncross phase freezes order
+//! entry (cancels are still accepted). Uncrossing executes every order that
+//! can trade at the single auction price, in price-time priority, and
+//! returns the book to continuous trading. Orders with the same owner never
+//! trade in the uncross either: when the auction would pair two, the book's
+//! self-trade prevention mode cuts them first and the auction is worked out
+//! again without them.
+
+use super::super::decimal::Decimal;
+use super::{BookCommand, Match, MatchReport, Order, OrderBook, OrderEvent, OrderType};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TradingPhase {
+    /// Orders match on arrival
+    #[default]
+    Continuous = 0,
+    /// Call phase: limit orders collect without matching
+    PreOpen = 1,
+    /// Call phase with order entry frozen, waiting for `uncross`
+    Uncross = 2,
+}
+
+impl From<u8> for TradingPhase {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => TradingPhase::PreOpen,
+            2 => TradingPhase::Uncross,
+            _ => TradingPhase::Continuous,
+        }
+    }
+}
+
+/// Price and volume an uncross would execute at now
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IndicativeUncross {
+    pub price: Decimal,
+    pub volume: Decimal,
+}
+
+#[wasm_bindgen]
+impl OrderBook {
+    /// Switch trading phase (0=Continuous, 1=PreOpen, 2=Uncross)
+    /// Switching to Continuous directly leaves crossed orders in the book;
+    /// use `uncross` to execute them.
+    pub fn set_trading_phase(&mut self, phase: u8) {
+        let phase = TradingPhase::from(phase);
+        self.journal(BookCommand::SetTradingPhase { phase });
+        self.phase = phase;
+    }
+
+    pub fn get_trading_phase(&self) -> u8 {
+        self.phase as u8
+    }
+
+    /// Indicative uncrossing price and volume (undefined if nothing would trade)
+    /// Returns `{ price, volume }`
+    pub fn get_indicative_uncross(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.indicative())?)
+    }
+
+    /// Execute the auction at the indicative price and resume continuous
+    /// trading. Throws outside a call phase.
+    /// Returns `{ matches, stp_cancelled, triggered_stops, expired }`.
+    pub fn uncross(&mut self) -> Result<JsValue, JsValue> {
+        if self.phase == TradingPhase::Continuous {
+            return Err(JsValue::from_str("Book is not in a call phase"));
+        }
+        self.journal(BookCommand::Uncross);
+        Ok(serde_wasm_bindgen::to_value(&self.run_uncross())?)
+    }
+}
+
+impl OrderBook {
+    pub(super) fn in_call_phase(&self) -> bool {
+        self.phase != TradingPhase::Continuous
+    }
+
+    /// Whether `order` may enter in the current phase
+    pub(super) fn phase_accepts(&self, order: &Order) -> bool {
+        match self.phase {
+            TradingPhase::Continuous => true,
+            TradingPhase::PreOpen => order.order_type == OrderType::Limit,
+            TradingPhase::Uncross => false,
+        }
+    }
+
+    /// Uncrossing price chosen by `AuctionSimulator`'s price rule over the
+    /// resting bid and ask levels: most volume, then least surplus, then
+    /// market pressure, else the midpoint of the tied range rounded down to
+    /// the tick. The rule runs on exact decimals. Iceberg reserves count;
+    /// parked stops do not.
+    pub(super) fn indicative(&self) -> Option<IndicativeUncross> {
+        let level = |orders: &Vec<Order>| {
+            let quantity = orders.iter().fold(Decimal::ZERO, |sum, o| {
+                sum.saturating_add(o.total_quantity())
+            });
+            Some((orders.first()?.price, quantity))
+        };
+        let bids: Vec<(Decimal, Decimal)> = self.bids.values().filter_map(level).collect();
+        let asks: Vec<(Decimal, Decimal)> = self.asks.values().filter_map(level).collect();
+        // Demand and supply at a price: levels are in curve order
+        let marketable = |levels: &[(Decimal, Decimal)], reaches: &dyn Fn(Decimal) -> bool| {
+            levels
+                .iter()
+                .take_while(|l| reaches(l.0))
+                .fold(Decimal::ZERO, |sum, l| sum.saturating_add(l.1))
+        };
+        let at = |p: Decimal| {
+            (
+                marketable(&bids, &|bid| bid >= p),
+                marketable(&asks, &|ask| ask <= p),
+            )
+        };
+
+        let mut candidates: Vec<Decimal> = bids.iter().chain(&asks).map(|l| l.0).collect();
+        candidates.sort();
+        candidates.dedup();
+        // (price, demand, supply); both sides are non-negative, so the
+        // surplus `|demand - supply|` cannot overflow
+        let scored: Vec<(Decimal, Decimal, Decimal)> = candidates
+            .into_iter()
+            .map(|p| {
+                let (demand, supply) = at(p);
+                (p, demand, supply)
+            })
+            .collect();
+        let volume = |c: &(Decimal, Decimal, Decimal)| c.1.min(c.2);
+        let surplus = |c: &(Decimal, Decimal, Decimal)| c.1.max(c.2) - c.1.min(c.2);
+        let max_volume = scored.iter().map(volume).max()?;
+        if !max_volume.is_positive() {
+            return None;
+        }
+        let best: Vec<_> = scored.iter().filter(|c| volume(c) == max_volume).collect();
+        let min_surplus = best.iter().map(|c| surplus(c)).min()?;
+        let best: Vec<_> = best
+            .into_iter()
+            .filter(|c| surplus(c) == min_surplus)
+            .collect();
+
+        let (low, high) = (best.first()?.0, best.last()?.0);
+        let price = if best.iter().all(|c| c.1 > c.2) {
+            high
+        } else if best.iter().all(|c| c.1 < c.2) {
+            low
+        } else {
+            let mid = low
+                .saturating_add(high)
+                .div_round(Decimal::new(2, 0), self.price_scale)?;
+            let tick = self.rules.tick_size.map_or(1, Decimal::units).max(1);
+            let snapped = Decimal::new(mid.units() - mid.units().rem_euclid(tick), mid.scale());
+            snapped.clamp(low, high)
+        };
+
+        // Any price in the tied range trades the rule's volume
+        let (demand, supply) = at(price);
+        let volume = demand.min(supply);
+        debug_assert_eq!(volume, max_volume);
+        Some(IndicativeUncross { price, volume })
+    }
+
+    pub(super) fn run_uncross(&mut self) -> MatchReport {
+        let mut report = MatchReport {
+            expired: self.expire_ids(),
+            ..MatchReport::default()
+        };
+        self.phase = TradingPhase::Continuous;
+
+        if let Some(UncrossFills {
+            price,
+            bids,
+            asks,
+            pairs,
+        }) = self.uncross_fills(&mut report)
+        {
+            for (b, a, quantity) in pairs {
+                let (buy, sell) = (&bids[b].0, &asks[a].0);
+                report.matches.push(Match {
+                    buy_order_id: buy.id,
+                    sell_order_id: sell.id,
+                    price,
+                    quantity,
+                    timestamp: self.clock,
+                    buy_owner: buy.owner,
+                    sell_owner: sell.owner,
+                });
+            }
+            for (order, filled) in bids.into_iter().chain(asks) {
+                self.fill_resting(&order, filled);
+            }
+            self.record_trades(&report.matches);
+        }
+
+        self.fire_stops(&mut report);
+        self.publish();
+        report
+    }
+
+    /// Price, fills and their pairing for the uncross. Whenever two orders
+    /// of the same owner would be paired, self-trade prevention cuts them as
+    /// if the newer had arrived against the older, and the auction is
+    /// worked out again; each pass removes at least one order.
+    fn uncross_fills(&mut self, report: &mut MatchReport) -> Option<UncrossFills> {
+        loop {
+            let IndicativeUncross { price, volume } = self.indicative()?;
+            let bids = allocate(self.bids.values().flatten(), volume);
+            let asks = allocate(self.asks.values().flatten(), volume);
+            let pairs = pair(&bids, &asks);
+            let own = pairs
+                .iter()
+                .map(|&(b, a, _)| (bids[b].0, asks[a].0))
+                .find(|(buy, sell)| buy.same_owner(sell));
+            let Some((buy, sell)) = own else {
+                return Some(UncrossFills {
+                    price,
+                    bids,
+                    asks,
+                    pairs,
+                });
+            };
+            let (newer, older) = if buy.sequence > sell.sequence {
+                (buy, sell)
+            } else {
+                (sell, buy)
+            };
+            let (newer_cut, older_cut) = self
+                .stp_mode
+                .cuts(newer.total_quantity(), older.total_quantity());
+            for (order, cut) in [(newer, newer_cut), (older, older_cut)] {
+                report.record_stp(order.id, cut);
+                if cut.is_positive() {
+                    self.fill_resting(&order, cut);
+                }
+            }
+        }
+    }
+
+    /// Take `filled` out of a resting order, keeping its queue position
+    fn fill_resting(&mut self, order: &Order, filled: Decimal) {
+        let remaining = order.total_quantity() - filled;
+        if !remaining.is_positive() {
+            self.unlink(order.id);
+            return;
+        }
+        let resting = self
+            .level_mut(order.side, order.price.units())
+            .and_then(|orders| orders.iter_mut().find(|o| o.id == order.id));
+        if let Some(resting) = resting {
+            resting.reduce_to(remaining);
+            let event = OrderEvent::changed(resting);
+            self.record(event);
+            self.sync_exposure(order.id);
+        }
+    }
+}
+
+/// Fills of an uncross at one price, with bid and ask fills paired up
+struct UncrossFills {
+    price: Decimal,
+    bids: Vec<(Order, Decimal)>,
+    asks: Vec<(Order, Decimal)>,
+    /// `(bid, ask, quantity)` indexes into the fills
+    pairs: Vec<(usize, usize, Decimal)>,
+}
+
+/// Pair bid and ask fills in priority order
+fn pair(bids: &[(Order, Decimal)], asks: &[(Order, Decimal)]) -> Vec<(usize, usize, Decimal)> {
+    let mut pairs = Vec::new();
+    let (mut b, mut a) = (0, 0);
+    let first = |fills: &[(Order, Decimal)]| fills.first().map_or(Decimal::ZERO, |f| f.1);
+    let (mut bid_left, mut ask_left) = (first(bids), first(asks));
+    while b < bids.len() && a < asks.len() {
+        let quantity = bid_left.min(ask_left);
+        pairs.push((b, a, quantity));
+        bid_left -= quantity;
+        ask_left -= quantity;
+        if bid_left.is_zero() {
+            b += 1;
+            bid_left = bids.get(b).map_or(Decimal::ZERO, |f| f.1);
+        }
+        if ask_left.is_zero() {
+            a += 1;
+            ask_left = asks.get(a).map_or(Decimal::ZERO, |f| f.1);
+        }
+    }
+    pairs
+}
+
+/// Fill `volume` from orders in priority order
+fn allocate<'a>(orders: impl Iterator<Item = &'a Order>, volume: Decimal) -> Vec<(Order, Decimal)> {
+    let mut left = volume;
+    let mut fills = Vec::new();
+    for order in orders {
+        if !left.is_positive() {
+            break;
+        }
+        let quantity = order.total_quantity().min(left);
+        left -= quantity;
+        fills.push((*order, quantity));
+    }
+    fills
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::auction::AuctionSimulator;
+    use super::super::{InstrumentRules, RejectReason, Side, StpCancel};
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn order(id: u32, side: Side, price: &str, qty: &str) -> Order {
+        Order::new(id, side, dec(price), dec(qty), id as u64)
+    }
+
+    #[test]
+    fn test_pre_open_collects_orders_and_uncross_trades_at_one_price() {
+        let mut book = OrderBook::new();
+        book.set_trading_phase(1);
+        book.submit(order(1, Side::Buy, "102", "3")).unwrap();
+        book.submit(order(2, Side::Buy, "100", "4")).unwrap();
+        book.submit(order(3, Side::Sell, "99", "2")).unwrap();
+        book.submit(order(4, Side::Sell, "101", "3")).unwrap();
+        assert_eq!((book.bid_count(), book.ask_count()), (2, 2));
+        let market = Order {
+            order_type: OrderType::Market,
+            ..order(5, Side::Buy, "0", "1")
+        };
+        assert_eq!(
+            book.submit(market).err(),
+            Some(RejectReason::NotAllowedInPhase {
+                phase: TradingPhase::PreOpen
+            })
+        );
+
+        // 3 trades at 101 or 102 with supply left over either way: the
+        // lower price, as AuctionSimulator picks
+        let indicative = book.indicative().unwrap();
+        assert_eq!(
+            (indicative.price, indicative.volume),
+            (dec("101"), dec("3"))
+        );
+        let mut auction = AuctionSimulator::new();
+        for o in book.bids.values().chain(book.asks.values()).flatten() {
+            let (price, quantity) = (o.price.to_f64(), o.quantity.to_f64());
+            auction.add_order(o.id, price, quantity, o.side == Side::Buy);
+        }
+        assert_eq!(auction.calculate_clearing_price(), vec![101.0, 3.0]);
+
+        book.phase = TradingPhase::Uncross;
+        assert!(book.submit(order(6, Side::Sell, "90", "1")).is_err());
+
+        let report = book.run_uncross();
+        let fills: Vec<(u32, u32, Decimal)> = report
+            .matches
+            .iter()
+            .map(|m| (m.buy_order_id, m.sell_order_id, m.quantity))
+            .collect();
+        assert_eq!(fills, vec![(1, 3, dec("2")), (1, 4, dec("1"))]);
+        assert!(report.matches.iter().all(|m| m.price == dec("101")));
+        assert_eq!(book.phase, TradingPhase::Continuous);
+        assert_eq!(book.best_bid(), Some(dec("100")));
+        assert_eq!(book.best_ask(), Some(dec("101")));
+        assert_eq!(
+            book.level(Side::Sell, dec("101.000000").units()).unwrap()[0].quantity,
+            dec("2")
+        );
+    }
+
+    #[test]
+    fn test_indicative_is_published_during_call_phase() {
+        let mut book = OrderBook::new();
+        book.set_market_data(true);
+        book.set_trading_phase(1);
+        book.submit(order(1, Side::Buy, "100", "5")).unwrap();
+        assert_eq!(book.updates[0].indicative, None);
+        book.submit(order(2, Side::Sell, "100", "2")).unwrap();
+        assert_eq!(
+            book.updates[1].indicative,
+            Some(IndicativeUncross {
+                price: dec("100"),
+                volume: dec("2"),
+            })
+        );
+
+        book.run_uncross();
+        assert_eq!(book.updates.last().unwrap().indicative, None);
+        // A one-sided book uncrosses to nothing
+        book.set_trading_phase(1);
+        assert!(book.run_uncross().matches.is_empty());
+        assert_eq!(
+            book.bids.values().flatten().next().unwrap().quantity,
+            dec("3")
+        );
+    }
+
+    #[test]
+    fn test_uncross_applies_self_trade_prevention() {
+        let owned = |id, side, price, qty| Order {
+            owner: Some(7),
+            ..order(id, side, price, qty)
+        };
+        let mut book = OrderBook::new();
+        book.set_trading_phase(1);
+        book.submit(owned(1, Side::Buy, "102", "3")).unwrap();
+        book.submit(owned(2, Side::Sell, "99", "2")).unwrap();
+        book.submit(order(3, Side::Sell, "101", "3")).unwrap();
+
+        // The newer own order is cancelled and the rest uncrosses without it
+        let report = book.run_uncross();
+        assert_eq!(
+            report.stp_cancelled,
+            vec![StpCancel {
+                order_id: 2,
+                quantity: dec("2"),
+            }]
+        );
+        let fills: Vec<(u32, u32, Decimal)> = report
+            .matches
+            .iter()
+            .map(|m| (m.buy_order_id, m.sell_order_id, m.quantity))
+            .collect();
+        assert_eq!(fills, vec![(1, 3, dec("3"))]);
+        assert_eq!((book.bid_count(), book.ask_count()), (0, 0));
+    }
+
+    #[test]
+    fn test_indicative_midpoint_snaps_to_tick() {
+        let mut book = OrderBook::new();
+        book.set_trading_phase(1);
+        book.submit(order(1, Side::Buy, "101", "5")).unwrap();
+        book.submit(order(2, Side::Sell, "100", "5")).unwrap();
+        // Balanced anywhere from 100 to 101: the midpoint, as AuctionSimulator
+        let indicative = book.indicative().unwrap();
+        assert_eq!(
+            (indicative.price, indicative.volume),
+            (dec("100.5"), dec("5"))
+        );
+
+        book.set_instrument_rules(InstrumentRules {
+            tick_size: Some(dec("1")),
+            ..InstrumentRules::default()
+        })
+        .unwrap();
+        let indicative = book.indicative().unwrap();
+        assert_eq!(
+            (indicative.price, indicative.volume),
+            (dec("100"), dec("5"))
+        );
+    }
+}
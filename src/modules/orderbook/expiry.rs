@@ -0,0 +1,110 @@
+//! Order Expiry
+//!
+//! Good-till-time and good-for-delivery-period orders. Expiry times are in
+//! milliseconds on the same clock as order timestamps. The book's clock
+//! advances with every incoming order and every `expire_orders` call, and
+//! expired orders are purged before any matching so they never fill.
+
+use super::{BookCommand, Order, OrderBook, RejectReason};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// How long an order stays in the book
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TimeInForce {
+    /// Rests until filled or cancelled
+    #[default]
+    GoodTillCancel = 0,
+    /// Expires at the order's `expires_at`
+    GoodTillTime = 1,
+    /// Expires when trading for the book's delivery period closes
+    GoodForDeliveryPeriod = 2,
+}
+
+impl From<u8> for TimeInForce {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => TimeInForce::GoodTillTime,
+            2 => TimeInForce::GoodForDeliveryPeriod,
+            _ => TimeInForce::GoodTillCancel,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl OrderBook {
+    /// Set when trading for this book's delivery period closes (ms);
+    /// GoodForDeliveryPeriod orders accepted afterwards expire then
+    pub fn set_gate_closure(&mut self, time_ms: u64) {
+        self.journal(BookCommand::SetGateClosure { time_ms });
+        self.gate_closure = Some(time_ms);
+    }
+
+    /// Remove every order (resting or stop) whose expiry is at or before `now`
+    /// Returns the purged orders, earliest expiry first
+    pub fn expire_orders(&mut self, now: u64) -> Result<JsValue, JsValue> {
+        self.journal(BookCommand::Expire { now });
+        let purged = self.expire(now);
+        self.publish();
+        Ok(serde_wasm_bindgen::to_value(&purged)?)
+    }
+}
+
+impl OrderBook {
+    /// Fill in `expires_at` from the time-in-force, rejecting orders that
+    /// need an expiry but have none, or that are already expired
+    pub(super) fn resolve_expiry(&self, mut order: Order) -> Result<Order, RejectReason> {
+        order.expires_at = match order.time_in_force {
+            TimeInForce::GoodTillCancel => None,
+            TimeInForce::GoodTillTime => Some(order.expires_at.ok_or(RejectReason::MissingExpiry)?),
+            TimeInForce::GoodForDeliveryPeriod => {
+                Some(self.gate_closure.ok_or(RejectReason::MissingExpiry)?)
+            }
+        };
+        if let Some(expires_at) = order.expires_at {
+            if expires_at <= self.clock.max(order.timestamp) {
+                return Err(RejectReason::Expired { expires_at });
+            }
+        }
+        Ok(order)
+    }
+
+    pub(super) fn advance_clock(&mut self, now: u64) {
+        self.clock = self.clock.max(now);
+    }
+
+    pub(super) fn track_expiry(&mut self, order: &Order) {
+        if let Some(expires_at) = order.expires_at {
+            self.expiries.insert((expires_at, order.id));
+        }
+    }
+
+    /// Purge orders expired at the current clock, returning their ids
+    pub(super) fn expire_ids(&mut self) -> Vec<u32> {
+        self.expire(self.clock).iter().map(|o| o.id).collect()
+    }
+
+    /// Purge orders expired as of `now`, advancing the clock
+    pub(super) fn expire(&mut self, now: u64) -> Vec<Order> {
+        self.advance_clock(now);
+        let mut purged = Vec::new();
+        while let Some(&(expires_at, order_id)) = self.expiries.first() {
+            if expires_at > self.clock {
+                break;
+            }
+            self.expiries.pop_first();
+            // Entries outlive filled orders, and ids can be reused
+            let current = self
+                .resting_order(order_id)
+                .or_else(|| self.stops.get(order_id));
+            if current.is_some_and(|o| o.expires_at == Some(expires_at)) {
+                let order = self
+                    .unlink(order_id)
+                    .or_else(|| self.stops.remove(order_id));
+                self.sync_exposure(order_id);
+                purged.extend(order);
+            }
+        }
+        purged
+    }
+}
@@ -0,0 +1,176 @@
+//! Market Impact
+//!
+//! Read-only sweep of the opposite side of the book, estimating what a market
+//! order of a given size would pay before it is submitted. Hidden iceberg
+//! reserves count, as matching would fill them; self-trade prevention and
+//! stops that the sweep would trigger are not simulated.
+
+use super::super::decimal::Decimal;
+use super::{Order, OrderBook, Side};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// Fill taken from one price level
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImpactLevel {
+    pub price: Decimal,
+    /// Quantity filled at this level
+    pub quantity: Decimal,
+    /// Quantity filled up to and including this level
+    pub cumulative_quantity: Decimal,
+    /// Average price up to and including this level
+    pub average_price: Decimal,
+}
+
+/// Expected outcome of sweeping the book for `requested`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImpactEstimate {
+    /// Side of the incoming order
+    pub side: Side,
+    pub requested: Decimal,
+    pub filled: Decimal,
+    /// Quantity the book cannot absorb
+    pub unfilled: Decimal,
+    pub average_price: Option<Decimal>,
+    /// Price of the last level reached
+    pub worst_price: Option<Decimal>,
+    pub mid_price: Option<Decimal>,
+    /// Average price against mid in basis points; positive is a cost
+    pub slippage_bps: Option<f64>,
+    pub levels: Vec<ImpactLevel>,
+}
+
+#[wasm_bindgen]
+impl OrderBook {
+    /// Estimate fills for a market order of `quantity` on `side` (0 = buy,
+    /// 1 = sell) without touching the book
+    /// Returns `{ side, requested, filled, unfilled, average_price, worst_price,
+    /// mid_price, slippage_bps, levels: [{ price, quantity, cumulative_quantity,
+    /// average_price }] }`
+    pub fn estimate_impact(&self, side: u8, quantity: f64) -> Result<JsValue, JsValue> {
+        let quantity = Decimal::from_f64(quantity, self.quantity_scale)
+            .filter(|q| q.is_positive())
+            .ok_or_else(|| JsValue::from_str("Quantity must be a positive number"))?;
+        let estimate = self.impact(Side::from(side), quantity);
+        Ok(serde_wasm_bindgen::to_value(&estimate)?)
+    }
+}
+
+impl OrderBook {
+    pub(super) fn impact(&self, side: Side, requested: Decimal) -> ImpactEstimate {
+        let levels: Box<dyn Iterator<Item = &Vec<Order>>> = match side {
+            Side::Buy => Box::new(self.asks.values()),
+            Side::Sell => Box::new(self.bids.values()),
+        };
+
+        let mut filled = Decimal::ZERO;
+        let mut notional = Decimal::ZERO;
+        let mut breakdown = Vec::new();
+        for orders in levels {
+            let remaining = requested - filled;
+            if !remaining.is_positive() {
+                break;
+            }
+            let Some(price) = orders.first().map(|o| o.price) else {
+                continue;
+            };
+            let available = orders
+                .iter()
+                .fold(Decimal::ZERO, |a, o| a.saturating_add(o.total_quantity()));
+            let quantity = available.min(remaining);
+            filled += quantity;
+            notional = notional.saturating_add(
+                price
+                    .mul_round(quantity, self.price_scale)
+                    .unwrap_or(Decimal::ZERO),
+            );
+            breakdown.push(ImpactLevel {
+                price,
+                quantity,
+                cumulative_quantity: filled,
+                average_price: notional
+                    .div_round(filled, self.price_scale)
+                    .unwrap_or(price),
+            });
+        }
+
+        let average_price = breakdown.last().map(|l| l.average_price);
+        let mid_price = self.mid();
+        let slippage_bps = match (average_price, mid_price) {
+            (Some(avg), Some(mid)) if !mid.is_zero() => {
+                let cost = match side {
+                    Side::Buy => avg - mid,
+                    Side::Sell => mid - avg,
+                };
+                Some(cost.to_f64() / mid.abs().to_f64() * 10_000.0)
+            }
+            _ => None,
+        };
+        ImpactEstimate {
+            side,
+            requested,
+            filled,
+            unfilled: requested - filled,
+            average_price,
+            worst_price: breakdown.last().map(|l| l.price),
+            mid_price,
+            slippage_bps,
+            levels: breakdown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_sweep_reports_levels_and_slippage() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 0, 99.0, 5.0, 1).unwrap();
+        book.add_order(2, 1, 101.0, 2.0, 2).unwrap();
+        book.add_order(3, 1, 101.0, 1.0, 3).unwrap();
+        book.add_order(4, 1, 103.0, 4.0, 4).unwrap();
+        let before = book.encode();
+
+        let estimate = book.impact(Side::Buy, dec("5"));
+        assert_eq!(estimate.filled, dec("5"));
+        assert!(estimate.unfilled.is_zero());
+        assert_eq!(estimate.levels.len(), 2);
+        assert_eq!(
+            estimate.levels[0],
+            ImpactLevel {
+                price: dec("101"),
+                quantity: dec("3"),
+                cumulative_quantity: dec("3"),
+                average_price: dec("101"),
+            }
+        );
+        // (3 * 101 + 2 * 103) / 5
+        assert_eq!(estimate.average_price, Some(dec("101.8")));
+        assert_eq!(estimate.worst_price, Some(dec("103")));
+        assert_eq!(estimate.mid_price, Some(dec("100")));
+        assert!((estimate.slippage_bps.unwrap() - 180.0).abs() < 1e-9);
+        assert_eq!(book.encode(), before);
+    }
+
+    #[test]
+    fn test_sweep_beyond_depth_reports_unfilled() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 0, 99.0, 2.0, 1).unwrap();
+
+        let estimate = book.impact(Side::Sell, dec("5"));
+        assert_eq!((estimate.filled, estimate.unfilled), (dec("2"), dec("3")));
+        assert_eq!(estimate.average_price, Some(dec("99")));
+        // No asks, so no mid to measure against
+        assert_eq!(estimate.slippage_bps, None);
+
+        let empty = book.impact(Side::Buy, dec("1"));
+        assert_eq!((empty.average_price, empty.worst_price), (None, None));
+        assert_eq!(empty.unfilled, dec("1"));
+    }
+}
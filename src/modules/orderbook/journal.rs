@@ -0,0 +1,326 @@
+//! Command Log
+//!
+//! Append-only, sequenced record of every command that changes a book, on
+//! top of a binary snapshot of the state when logging started. Matching is
+//! deterministic, so replaying the log rebuilds the book and its fills
+//! exactly. Commands are recorded as received, including ones the book then
+//! rejects, since a rejected order can still advance the book clock.
+//! Market data, trade history settings and replica updates are not logged;
+//! they do not affect matching.
+
+use super::{
+    AllocationPolicy, InstrumentRules, Order, OrderBook, RiskLimits, SelfTradePrevention,
+    TradingPhase,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// A book mutation as issued through the public API
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum BookCommand {
+    /// `add_order` / `add_order_with_type`
+    Add {
+        order: Order,
+    },
+    /// `submit_order` / `place_order`
+    Submit {
+        order: Order,
+    },
+    /// `load_orders`, after validation
+    Load {
+        orders: Vec<Order>,
+    },
+    Cancel {
+        order_id: u32,
+    },
+    Amend {
+        order_id: u32,
+        price: f64,
+        quantity: f64,
+    },
+    Match,
+    Expire {
+        now: u64,
+    },
+    Clear,
+    SetRules {
+        rules: InstrumentRules,
+    },
+    SetSelfTradePrevention {
+        mode: SelfTradePrevention,
+    },
+    SetAllocation {
+        policy: AllocationPolicy,
+    },
+    SetGateClosure {
+        time_ms: u64,
+    },
+    /// `set_account_limits` / `clear_account_limits`
+    SetAccountLimits {
+        account: u32,
+        limits: Option<RiskLimits>,
+    },
+    SetTradingPhase {
+        phase: TradingPhase,
+    },
+    Uncross,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoggedCommand {
+    /// Position in the log, starting at 1
+    pub sequence: u64,
+    pub command: BookCommand,
+}
+
+/// Starting state plus every command applied since
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommandLog {
+    /// Binary snapshot (`to_bytes`) taken when logging started
+    pub base: Vec<u8>,
+    pub commands: Vec<LoggedCommand>,
+}
+
+impl CommandLog {
+    fn push(&mut self, command: BookCommand) {
+        self.commands.push(LoggedCommand {
+            sequence: self.commands.len() as u64 + 1,
+            command,
+        });
+    }
+}
+
+#[wasm_bindgen]
+impl OrderBook {
+    /// Start a new command log from the book's current state, discarding any
+    /// previous log
+    pub fn start_command_log(&mut self) {
+        self.command_log = Some(CommandLog {
+            base: self.encode(),
+            commands: Vec::new(),
+        });
+    }
+
+    /// Stop logging and discard the log
+    pub fn stop_command_log(&mut self) {
+        self.command_log = None;
+    }
+
+    /// The current log, or undefined when not logging
+    /// Returns `{ base, commands: [{ sequence, command: { type, ... } }] }`
+    pub fn get_command_log(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.command_log)?)
+    }
+
+    /// Rebuild a book by replaying a log from `get_command_log`
+    /// The replayed book keeps logging, so its log equals the input.
+    pub fn replay(log: JsValue) -> Result<OrderBook, JsValue> {
+        let log: CommandLog = serde_wasm_bindgen::from_value(log)?;
+        OrderBook::replay_log(&log).map_err(|e| JsValue::from_str(&e))
+    }
+}
+
+impl OrderBook {
+    /// Append to the log if logging
+    pub(super) fn journal(&mut self, command: BookCommand) {
+        if let Some(log) = &mut self.command_log {
+            log.push(command);
+        }
+    }
+
+    pub(super) fn replay_log(log: &CommandLog) -> Result<OrderBook, String> {
+        let mut book = OrderBook::decode(&log.base)?;
+        book.command_log = Some(CommandLog {
+            base: log.base.clone(),
+            commands: Vec::new(),
+        });
+        for (expected, entry) in (1..).zip(&log.commands) {
+            if entry.sequence != expected {
+                return Err(format!(
+                    "Command log gap: expected sequence {}, got {}",
+                    expected, entry.sequence
+                ));
+            }
+            book.apply(entry.command.clone());
+        }
+        Ok(book)
+    }
+
+    /// Log and run one command, discarding its result; rejections replay as
+    /// rejections
+    fn apply(&mut self, command: BookCommand) {
+        self.journal(command.clone());
+        match command {
+            BookCommand::Add { order } => {
+                let _ = self.execute(order);
+            }
+            BookCommand::Submit { order } => {
+                let _ = self.submit(order);
+            }
+            BookCommand::Load { orders } => self.load(orders),
+            BookCommand::Cancel { order_id } => {
+                self.cancel(order_id);
+            }
+            BookCommand::Amend {
+                order_id,
+                price,
+                quantity,
+            } => {
+                let _ = self.amend(order_id, price, quantity);
+            }
+            BookCommand::Match => {
+                self.match_crossed();
+            }
+            BookCommand::Expire { now } => {
+                self.expire(now);
+                self.publish();
+            }
+            BookCommand::Clear => self.clear_orders(),
+            BookCommand::SetRules { rules } => {
+                let _ = self.set_instrument_rules(rules);
+            }
+            BookCommand::SetSelfTradePrevention { mode } => self.stp_mode = mode,
+            BookCommand::SetAllocation { policy } => self.allocation = policy,
+            BookCommand::SetGateClosure { time_ms } => self.gate_closure = Some(time_ms),
+            BookCommand::SetAccountLimits { account, limits } => {
+                let _ = self.set_limits(account, limits);
+            }
+            BookCommand::SetTradingPhase { phase } => self.phase = phase,
+            BookCommand::Uncross => {
+                self.run_uncross();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{OrderType, Side, TimeInForce};
+    use super::*;
+    use proptest::prelude::*;
+
+    // Orders drawn from a narrow band of prices and ids so streams cross,
+    // collide on ids and self-trade often
+    fn arb_order() -> impl Strategy<Value = Order> {
+        (
+            1u32..40,
+            any::<bool>(),
+            95i64..=105,
+            1i64..=8,
+            0u8..4,
+            proptest::option::of(0u32..3),
+            proptest::option::of(93i64..=107),
+            proptest::option::of(1i64..=3),
+            proptest::option::of(1u64..=60),
+        )
+            .prop_map(
+                |(id, buy, price, qty, order_type, owner, stop, peak, ttl)| Order {
+                    order_type: OrderType::from(order_type),
+                    owner,
+                    stop_price: stop.map(|s| s.to_string().parse().unwrap()),
+                    display_quantity: peak.map(|p| p.to_string().parse().unwrap()),
+                    time_in_force: if ttl.is_some() {
+                        TimeInForce::GoodTillTime
+                    } else {
+                        TimeInForce::GoodTillCancel
+                    },
+                    expires_at: ttl,
+                    ..Order::new(
+                        id,
+                        if buy { Side::Buy } else { Side::Sell },
+                        price.to_string().parse().unwrap(),
+                        qty.to_string().parse().unwrap(),
+                        0,
+                    )
+                },
+            )
+    }
+
+    fn arb_command() -> impl Strategy<Value = BookCommand> {
+        prop_oneof![
+            4 => arb_order().prop_map(|order| BookCommand::Submit { order }),
+            2 => arb_order().prop_map(|order| BookCommand::Add { order }),
+            1 => (1u32..40).prop_map(|order_id| BookCommand::Cancel { order_id }),
+            1 => (1u32..40, 95i64..=105, 1i64..=8).prop_map(|(order_id, p, q)| BookCommand::Amend {
+                order_id,
+                price: p as f64,
+                quantity: q as f64,
+            }),
+            1 => Just(BookCommand::Match),
+            1 => (0u8..3).prop_map(|p| BookCommand::SetTradingPhase {
+                phase: TradingPhase::from(p),
+            }),
+            1 => Just(BookCommand::Uncross),
+            1 => (0u8..4).prop_map(|m| BookCommand::SetSelfTradePrevention {
+                mode: SelfTradePrevention::from(m),
+            }),
+            1 => (0u32..3, proptest::option::of(1i64..=30)).prop_map(|(account, max)| {
+                BookCommand::SetAccountLimits {
+                    account,
+                    limits: max.map(|m| RiskLimits {
+                        max_position: Some(m.to_string().parse().unwrap()),
+                        ..RiskLimits::default()
+                    }),
+                }
+            }),
+        ]
+    }
+
+    /// Run commands the way the public API does, stamping increasing times
+    fn run(book: &mut OrderBook, commands: Vec<BookCommand>) {
+        for (i, mut command) in commands.into_iter().enumerate() {
+            if let BookCommand::Add { order } | BookCommand::Submit { order } = &mut command {
+                order.timestamp = i as u64 * 10;
+                order.expires_at = order.expires_at.map(|ttl| order.timestamp + ttl);
+            }
+            book.apply(command);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_replay_rebuilds_book_and_fills(
+            warmup in proptest::collection::vec(arb_command(), 0..20),
+            commands in proptest::collection::vec(arb_command(), 1..80),
+        ) {
+            let mut book = OrderBook::new();
+            run(&mut book, warmup);
+            let fills_before = book.trades.recent(usize::MAX).len();
+            book.start_command_log();
+            run(&mut book, commands);
+
+            let log = book.command_log.clone().unwrap();
+            let replayed = OrderBook::replay_log(&log).unwrap();
+            prop_assert_eq!(replayed.encode(), book.encode());
+            prop_assert_eq!(
+                replayed.trades.recent(usize::MAX),
+                book.trades.recent(usize::MAX)[fills_before..].to_vec()
+            );
+            prop_assert_eq!(replayed.command_log.as_ref(), Some(&log));
+        }
+    }
+
+    #[test]
+    fn test_log_is_sequenced_and_gaps_are_rejected() {
+        let mut book = OrderBook::new();
+        book.add_order(1, 1, 100.0, 1.0, 1).unwrap();
+        book.start_command_log();
+        book.add_order(2, 0, 100.0, 1.0, 2).unwrap();
+        book.cancel_order(1);
+        book.apply(BookCommand::Match);
+        // Rejected as a duplicate, but still logged
+        let duplicate = Order::new(2, Side::Buy, "99".parse().unwrap(), "1".parse().unwrap(), 3);
+        book.apply(BookCommand::Add { order: duplicate });
+
+        let mut log = book.command_log.clone().unwrap();
+        let sequences: Vec<u64> = log.commands.iter().map(|c| c.sequence).collect();
+        assert_eq!(sequences, vec![1, 2, 3, 4]);
+        assert_eq!(log.commands[1].command, BookCommand::Cancel { order_id: 1 });
+        let replayed = OrderBook::replay_log(&log).unwrap();
+        assert_eq!((replayed.bid_count(), replayed.ask_count()), (1, 0));
+
+        log.commands.remove(1);
+        assert!(OrderBook::replay_log(&log).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Incremental Market Data
+//!
+//! Level-2 (aggregated price level) and level-3 (individual order) updates
+//! published after each book mutation. Every update carries a feed sequence
+//! number, so a replica can be rebuilt from a snapshot plus the updates that
+//! follow it and gaps are detected instead of silently corrupting the copy.
+
+use super::super::decimal::{Decimal, MAX_SCALE};
+use super::{IndicativeUncross, Order, OrderBook, Side};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// Order-level (L3) change
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum OrderEvent {
+    /// Order rested at the back of its price level
+    Add { order: Order },
+    /// Open quantity changed in place; queue position is unchanged
+    Modify {
+        order_id: u32,
+        side: Side,
+        price: Decimal,
+        quantity: Decimal,
+    },
+    /// Order left the book (filled, cancelled or moved to another level)
+    Delete {
+        order_id: u32,
+        side: Side,
+        price: Decimal,
+    },
+}
+
+impl OrderEvent {
+    /// Event for a resting order whose quantity was reduced
+    pub(super) fn changed(order: &Order) -> Self {
+        if order.quantity.is_positive() {
+            OrderEvent::Modify {
+                order_id: order.id,
+                side: order.side,
+                price: order.price,
+                quantity: order.quantity,
+            }
+        } else {
+            OrderEvent::Delete {
+                order_id: order.id,
+                side: order.side,
+                price: order.price,
+            }
+        }
+    }
+
+    fn level(&self) -> (Side, Decimal) {
+        match *self {
+            OrderEvent::Add { order } => (order.side, order.price),
+            OrderEvent::Modify { side, price, .. } | OrderEvent::Delete { side, price, .. } => {
+                (side, price)
+            }
+        }
+    }
+}
+
+/// Price-level (L2) state after an update; zero quantity means the level is gone
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LevelUpdate {
+    pub side: Side,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub order_count: usize,
+}
+
+/// Everything one book operation changed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MarketDataUpdate {
+    /// Feed sequence number, incremented by one per update
+    pub sequence: u64,
+    pub orders: Vec<OrderEvent>,
+    pub levels: Vec<LevelUpdate>,
+    /// Indicative uncross during a call phase
+    #[serde(default)]
+    pub indicative: Option<IndicativeUncross>,
+}
+
+/// Public book state as of feed sequence `sequence`; iceberg reserves are
+/// not included
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    pub sequence: u64,
+    pub price_scale: u8,
+    pub quantity_scale: u8,
+    /// Best level first, each level in queue order
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+}
+
+#[wasm_bindgen]
+impl OrderBook {
+    /// Start or stop recording incremental market data updates
+    pub fn set_market_data(&mut self, enabled: bool) {
+        self.market_data = enabled;
+        if !enabled {
+            self.pending_events.clear();
+            self.updates.clear();
+        }
+    }
+
+    /// Take all updates published since the last call
+    /// Returns `[{ sequence, orders, levels }]`
+    pub fn drain_market_data(&mut self) -> Result<JsValue, JsValue> {
+        let updates = std::mem::take(&mut self.updates);
+        Ok(serde_wasm_bindgen::to_value(&updates)?)
+    }
+
+    /// Full book snapshot to seed a replica
+    /// Returns `{ sequence, price_scale, quantity_scale, bids, asks }`
+    pub fn get_snapshot(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.snapshot())?)
+    }
+
+    /// Rebuild a book from a snapshot produced by `get_snapshot`
+    pub fn from_snapshot(snapshot: JsValue) -> Result<OrderBook, JsValue> {
+        let snapshot: BookSnapshot = serde_wasm_bindgen::from_value(snapshot)?;
+        OrderBook::restore(snapshot).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Apply updates from `drain_market_data` to a replica
+    /// Updates at or before the replica's sequence are skipped; a gap in
+    /// sequence numbers throws.
+    pub fn apply_market_data(&mut self, updates: JsValue) -> Result<(), JsValue> {
+        let updates: Vec<MarketDataUpdate> = serde_wasm_bindgen::from_value(updates)?;
+        for update in &updates {
+            self.apply_update(update)
+                .map_err(|e| JsValue::from_str(&e))?;
+        }
+        Ok(())
+    }
+}
+
+impl OrderBook {
+    pub(super) fn record(&mut self, event: OrderEvent) {
+        if self.market_data {
+            self.pending_events.push(event);
+        }
+    }
+
+    /// Close the current operation, publishing its events with the resulting
+    /// level states as one sequenced update
+    pub(super) fn publish(&mut self) {
+        if self.pending_events.is_empty() {
+            return;
+        }
+        let orders = std::mem::take(&mut self.pending_events);
+        let mut touched: Vec<(Side, Decimal)> = Vec::new();
+        for event in &orders {
+            let level = event.level();
+            if !touched.contains(&level) {
+                touched.push(level);
+            }
+        }
+        let levels = touched
+            .into_iter()
+            .map(|(side, price)| self.level_update(side, price))
+            .collect();
+
+        self.feed_sequence += 1;
+        self.updates.push(MarketDataUpdate {
+            sequence: self.feed_sequence,
+            orders,
+            levels,
+            indicative: self.in_call_phase().then(|| self.indicative()).flatten(),
+        });
+    }
+
+    fn level_update(&self, side: Side, price: Decimal) -> LevelUpdate {
+        let orders = self
+            .level(side, price.units())
+            .map_or(&[][..], Vec::as_slice);
+        LevelUpdate {
+            side,
+            price,
+            quantity: orders.iter().map(|o| o.quantity).sum(),
+            order_count: orders.len(),
+        }
+    }
+
+    pub(super) fn snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            sequence: self.feed_sequence,
+            price_scale: self.price_scale,
+            quantity_scale: self.quantity_scale,
+            bids: self.bids.values().flatten().map(Order::public).collect(),
+            asks: self.asks.values().flatten().map(Order::public).collect(),
+        }
+    }
+
+    pub(super) fn restore(snapshot: BookSnapshot) -> Result<OrderBook, String> {
+        if snapshot.price_scale > MAX_SCALE || snapshot.quantity_scale > MAX_SCALE {
+            return Err(format!(
+                "Precision must be at most {} decimal places",
+                MAX_SCALE
+            ));
+        }
+        let mut book = OrderBook {
+            price_scale: snapshot.price_scale,
+            quantity_scale: snapshot.quantity_scale,
+            feed_sequence: snapshot.sequence,
+            ..OrderBook::new()
+        };
+        for order in snapshot.bids.into_iter().chain(snapshot.asks) {
+            let order = book
+                .normalize(order)
+                .map_err(|reason| format!("Order {} rejected: {:?}", order.id, reason))?;
+            if book.order_index.contains_key(&order.id) {
+                return Err(format!("Duplicate order id {}", order.id));
+            }
+            book.insert(order);
+        }
+        Ok(book)
+    }
+
+    /// Apply one update from another book's feed, republishing it if this
+    /// book records market data itself
+    pub(super) fn apply_update(&mut self, update: &MarketDataUpdate) -> Result<(), String> {
+        if update.sequence <= self.feed_sequence {
+            return Ok(());
+        }
+        if update.sequence != self.feed_sequence + 1 {
+            return Err(format!(
+                "Market data gap: expected sequence {}, got {}",
+                self.feed_sequence + 1,
+                update.sequence
+            ));
+        }
+
+        for event in &update.orders {
+            match *event {
+                OrderEvent::Add { order } => {
+                    if self.order_index.contains_key(&order.id) {
+                        return Err(format!("Duplicate order id {}", order.id));
+                    }
+                    self.insert(order);
+                }
+                OrderEvent::Modify {
+                    order_id,
+                    side,
+                    price,
+                    quantity,
+                } => {
+                    let order = self
+                        .level_mut(side, price.units())
+                        .and_then(|orders| orders.iter_mut().find(|o| o.id == order_id))
+                        .ok_or_else(|| format!("Unknown order id {}", order_id))?;
+                    order.quantity = quantity;
+                    let event = OrderEvent::changed(order);
+                    self.record(event);
+                    self.sync_exposure(order_id);
+                }
+                OrderEvent::Delete { order_id, .. } => {
+                    self.unlink(order_id)
+                        .ok_or_else(|| format!("Unknown order id {}", order_id))?;
+                }
+            }
+        }
+
+        self.publish();
+        // Keep in step with the source even when this book records nothing
+        self.feed_sequence = update.sequence;
+        Ok(())
+    }
+}
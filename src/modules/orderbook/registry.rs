@@ -0,0 +1,293 @@
+//! Instrument Registry
+//!
+//! Owns one order book per tradable product, keyed by delivery slot and grid
+//! zone (e.g. each 15-minute slot in each zone). Gives cross-book views of
+//! the top of book and closes every book of a delivery slot at once.
+
+use super::super::decimal::Decimal;
+use super::{Order, OrderBook};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+/// Product traded in one book: a delivery slot in a grid zone
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct InstrumentId {
+    /// Start of the delivery period (ms)
+    pub delivery_slot: u64,
+    pub zone: String,
+}
+
+impl fmt::Display for InstrumentId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.zone, self.delivery_slot)
+    }
+}
+
+/// Best prices of one instrument
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TopOfBook {
+    pub instrument: InstrumentId,
+    pub best_bid: Option<Decimal>,
+    pub best_ask: Option<Decimal>,
+}
+
+/// Orders left in a book when its delivery slot closed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClosedInstrument {
+    pub instrument: InstrumentId,
+    /// Resting and stop orders cancelled by the close
+    pub cancelled: Vec<Order>,
+    pub last_price: Option<Decimal>,
+}
+
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct MarketRegistry {
+    // Ordered by delivery slot, then zone
+    books: BTreeMap<InstrumentId, OrderBook>,
+}
+
+#[wasm_bindgen]
+impl MarketRegistry {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a configured book for an instrument
+    /// A book without a gate closure gets the start of its delivery slot, so
+    /// GoodForDeliveryPeriod orders expire when delivery begins.
+    pub fn add_instrument(
+        &mut self,
+        delivery_slot: u64,
+        zone: String,
+        book: OrderBook,
+    ) -> Result<(), JsValue> {
+        self.add(
+            InstrumentId {
+                delivery_slot,
+                zone,
+            },
+            book,
+        )
+        .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Take an instrument's book out of the registry
+    pub fn remove_instrument(&mut self, delivery_slot: u64, zone: String) -> Option<OrderBook> {
+        self.books.remove(&InstrumentId {
+            delivery_slot,
+            zone,
+        })
+    }
+
+    pub fn instrument_count(&self) -> usize {
+        self.books.len()
+    }
+
+    /// All instruments, by delivery slot then zone
+    /// Returns `[{ delivery_slot, zone }]`
+    pub fn get_instruments(&self) -> Result<JsValue, JsValue> {
+        let ids: Vec<&InstrumentId> = self.books.keys().collect();
+        Ok(serde_wasm_bindgen::to_value(&ids)?)
+    }
+
+    /// Submit an order to an instrument's book; see `OrderBook::place_order`
+    pub fn place_order(
+        &mut self,
+        delivery_slot: u64,
+        zone: String,
+        order: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        self.book_mut(delivery_slot, zone)?.place_order(order)
+    }
+
+    pub fn cancel_order(
+        &mut self,
+        delivery_slot: u64,
+        zone: String,
+        order_id: u32,
+    ) -> Result<bool, JsValue> {
+        Ok(self.book_mut(delivery_slot, zone)?.cancel_order(order_id))
+    }
+
+    /// Depth of one instrument's book; see `OrderBook::get_depth`
+    pub fn get_depth(
+        &self,
+        delivery_slot: u64,
+        zone: String,
+        levels: usize,
+    ) -> Result<JsValue, JsValue> {
+        let id = InstrumentId {
+            delivery_slot,
+            zone,
+        };
+        self.books
+            .get(&id)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown instrument {}", id)))?
+            .get_depth(levels)
+    }
+
+    /// Best bid and ask of every instrument
+    /// Returns `[{ instrument, best_bid, best_ask }]`
+    pub fn get_top_of_book(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.top_of_book())?)
+    }
+
+    /// Instruments whose best bid is at or above their best ask
+    pub fn get_crossed_instruments(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.crossed())?)
+    }
+
+    /// Close every delivery slot starting at or before `delivery_slot`,
+    /// removing their books and cancelling what was left in them
+    /// Returns `[{ instrument, cancelled, last_price }]`
+    pub fn close_delivery_slots(&mut self, delivery_slot: u64) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.close_slots(delivery_slot),
+        )?)
+    }
+}
+
+impl MarketRegistry {
+    pub(super) fn add(&mut self, id: InstrumentId, mut book: OrderBook) -> Result<(), String> {
+        if self.books.contains_key(&id) {
+            return Err(format!("Instrument {} already exists", id));
+        }
+        // Through the setter, so a command log replays the same gate closure
+        if book.gate_closure.is_none() {
+            book.set_gate_closure(id.delivery_slot);
+        }
+        self.books.insert(id, book);
+        Ok(())
+    }
+
+    fn book_mut(&mut self, delivery_slot: u64, zone: String) -> Result<&mut OrderBook, JsValue> {
+        let id = InstrumentId {
+            delivery_slot,
+            zone,
+        };
+        self.books
+            .get_mut(&id)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown instrument {}", id)))
+    }
+
+    pub(super) fn top_of_book(&self) -> Vec<TopOfBook> {
+        self.books
+            .iter()
+            .map(|(id, book)| TopOfBook {
+                instrument: id.clone(),
+                best_bid: book.best_bid(),
+                best_ask: book.best_ask(),
+            })
+            .collect()
+    }
+
+    pub(super) fn crossed(&self) -> Vec<InstrumentId> {
+        self.books
+            .iter()
+            .filter(|(_, book)| match (book.best_bid(), book.best_ask()) {
+                (Some(bid), Some(ask)) => bid >= ask,
+                _ => false,
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    pub(super) fn close_slots(&mut self, delivery_slot: u64) -> Vec<ClosedInstrument> {
+        let open = self.books.split_off(&InstrumentId {
+            delivery_slot: delivery_slot.saturating_add(1),
+            zone: String::new(),
+        });
+        let closed = std::mem::replace(&mut self.books, open);
+        closed
+            .into_iter()
+            .map(|(instrument, book)| ClosedInstrument {
+                instrument,
+                cancelled: book
+                    .bids
+                    .values()
+                    .chain(book.asks.values())
+                    .flatten()
+                    .chain(book.stops.orders())
+                    .copied()
+                    .collect(),
+                last_price: book.trades.last_price(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Side;
+    use super::*;
+
+    fn id(delivery_slot: u64, zone: &str) -> InstrumentId {
+        InstrumentId {
+            delivery_slot,
+            zone: zone.to_string(),
+        }
+    }
+
+    fn order(id: u32, side: Side, price: &str) -> Order {
+        Order::new(id, side, price.parse().unwrap(), "1".parse().unwrap(), 1)
+    }
+
+    #[test]
+    fn test_cross_book_queries() {
+        let mut registry = MarketRegistry::new();
+        // A logging book replays to the gate closure the registry gave it
+        let mut logged = OrderBook::new();
+        logged.start_command_log();
+        registry.add(id(900_000, "north"), logged).unwrap();
+        let log = registry.books[&id(900_000, "north")]
+            .command_log
+            .clone()
+            .unwrap();
+        assert_eq!(
+            OrderBook::replay_log(&log).unwrap().gate_closure,
+            Some(900_000)
+        );
+        let mut crossed = OrderBook::new();
+        crossed.add_order(1, 0, 101.0, 1.0, 1).unwrap();
+        crossed.add_order(2, 1, 100.0, 1.0, 2).unwrap();
+        registry.add(id(0, "south"), crossed).unwrap();
+        assert!(registry.add(id(0, "south"), OrderBook::new()).is_err());
+
+        let book = registry.books.get_mut(&id(900_000, "north")).unwrap();
+        book.submit(order(1, Side::Buy, "99")).unwrap();
+        assert_eq!(book.gate_closure, Some(900_000));
+
+        let top = registry.top_of_book();
+        assert_eq!(top[0].instrument, id(0, "south"));
+        assert_eq!(top[1].best_bid, Some("99".parse().unwrap()));
+        assert_eq!(top[1].best_ask, None);
+        assert_eq!(registry.crossed(), vec![id(0, "south")]);
+    }
+
+    #[test]
+    fn test_close_slots_removes_books_up_to_slot() {
+        let mut registry = MarketRegistry::new();
+        for (slot, zone) in [(0, "north"), (0, "south"), (900_000, "north")] {
+            registry.add(id(slot, zone), OrderBook::new()).unwrap();
+        }
+        let book = registry.books.get_mut(&id(0, "south")).unwrap();
+        book.submit(order(1, Side::Sell, "100")).unwrap();
+        book.submit(Order {
+            stop_price: Some("90".parse().unwrap()),
+            ..order(2, Side::Sell, "89")
+        })
+        .unwrap();
+
+        let closed = registry.close_slots(0);
+        let ids: Vec<&InstrumentId> = closed.iter().map(|c| &c.instrument).collect();
+        assert_eq!(ids, vec![&id(0, "north"), &id(0, "south")]);
+        let cancelled: Vec<u32> = closed[1].cancelled.iter().map(|o| o.id).collect();
+        assert_eq!(cancelled, vec![1, 2]);
+        assert_eq!(registry.instrument_count(), 1);
+        assert!(registry.close_slots(899_999).is_empty());
+    }
+}
@@ -0,0 +1,447 @@
+//! Pre-trade Risk
+//!
+//! Per-account limits checked when an order is admitted or amended. An
+//! account is an order's `owner`; orders without one are not checked. Net
+//! positions are built from this book's fills (buys add, sells subtract).
+//! The position check assumes every open order on the same side fills, so an
+//! account can never be driven past its limit by its own resting orders.
+//! Open quantity and notional are kept as running per-account totals, so a
+//! check costs the same however many orders the book holds.
+
+use super::super::decimal::Decimal;
+use super::{BookCommand, Match, MatchReport, Order, OrderBook, OrderType, RejectReason, Side};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use wasm_bindgen::prelude::*;
+
+/// Limits for one account; unset fields are not enforced
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RiskLimits {
+    /// Maximum total `|price| * quantity` of open limit orders, counting
+    /// stop-limits and iceberg reserves
+    #[serde(default)]
+    pub max_open_notional: Option<Decimal>,
+    /// Maximum net position in kWh, long or short, with open orders counted
+    /// as filled
+    #[serde(default)]
+    pub max_position: Option<Decimal>,
+}
+
+/// An account's exposure in this book
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccountRisk {
+    pub account: u32,
+    /// Net filled quantity; positive is long (bought)
+    pub position: Decimal,
+    pub open_buy_quantity: Decimal,
+    pub open_sell_quantity: Decimal,
+    pub open_notional: Decimal,
+    pub limits: Option<RiskLimits>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct RiskBook {
+    pub(super) limits: BTreeMap<u32, RiskLimits>,
+    pub(super) positions: BTreeMap<u32, Decimal>,
+    /// What each owned resting or stop order adds to `open`, by order id
+    exposures: HashMap<u32, Exposure>,
+    open: BTreeMap<u32, OpenTotals>,
+}
+
+/// One order's share of its account's open totals, in units of the book's
+/// quantity and price precision
+#[derive(Clone, Copy, Debug)]
+struct Exposure {
+    account: u32,
+    side: Side,
+    quantity: i128,
+    notional: i128,
+}
+
+/// Kept wider than `Decimal` so adding and removing orders stays exact
+#[derive(Clone, Copy, Debug, Default)]
+struct OpenTotals {
+    buy_quantity: i128,
+    sell_quantity: i128,
+    notional: i128,
+}
+
+impl RiskBook {
+    /// Forget every open order, keeping limits and positions
+    pub(super) fn clear_open(&mut self) {
+        self.exposures.clear();
+        self.open.clear();
+    }
+
+    fn apply(&mut self, exposure: Exposure, sign: i128) {
+        let totals = self.open.entry(exposure.account).or_default();
+        match exposure.side {
+            Side::Buy => totals.buy_quantity += sign * exposure.quantity,
+            Side::Sell => totals.sell_quantity += sign * exposure.quantity,
+        }
+        totals.notional += sign * exposure.notional;
+        if totals.buy_quantity == 0 && totals.sell_quantity == 0 && totals.notional == 0 {
+            self.open.remove(&exposure.account);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl OrderBook {
+    /// Set an account's limits
+    /// Expects `{ max_open_notional?, max_position? }`
+    pub fn set_account_limits(&mut self, account: u32, limits: JsValue) -> Result<(), JsValue> {
+        let limits: RiskLimits = serde_wasm_bindgen::from_value(limits)?;
+        self.set_limits(account, Some(limits))
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.journal(BookCommand::SetAccountLimits {
+            account,
+            limits: self.risk.limits.get(&account).copied(),
+        });
+        Ok(())
+    }
+
+    /// Stop checking an account's orders
+    pub fn clear_account_limits(&mut self, account: u32) {
+        self.journal(BookCommand::SetAccountLimits {
+            account,
+            limits: None,
+        });
+        self.risk.limits.remove(&account);
+    }
+
+    /// Position, open orders and limits of an account
+    /// Returns `{ account, position, open_buy_quantity, open_sell_quantity,
+    /// open_notional, limits }`
+    pub fn get_account_risk(&self, account: u32) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.account_risk(account, None),
+        )?)
+    }
+}
+
+impl OrderBook {
+    /// Store limits at the book's precision, or remove them with `None`
+    pub(super) fn set_limits(
+        &mut self,
+        account: u32,
+        limits: Option<RiskLimits>,
+    ) -> Result<(), String> {
+        let Some(limits) = limits else {
+            self.risk.limits.remove(&account);
+            return Ok(());
+        };
+        let at_scale = |value: Option<Decimal>, scale: u8, name: &str| {
+            value
+                .map(|v| {
+                    v.rescale(scale)
+                        .filter(|v| !v.is_negative())
+                        .ok_or_else(|| {
+                            format!("{} {} is negative or finer than book precision", name, v)
+                        })
+                })
+                .transpose()
+        };
+        let limits = RiskLimits {
+            max_open_notional: at_scale(
+                limits.max_open_notional,
+                self.price_scale,
+                "max_open_notional",
+            )?,
+            max_position: at_scale(limits.max_position, self.quantity_scale, "max_position")?,
+        };
+        self.risk.limits.insert(account, limits);
+        Ok(())
+    }
+
+    /// Exposure from the account's resting and stop orders, leaving out
+    /// `excluding` (an order being replaced)
+    pub(super) fn account_risk(&self, account: u32, excluding: Option<u32>) -> AccountRisk {
+        let mut open = self.risk.open.get(&account).copied().unwrap_or_default();
+        let replaced = excluding
+            .and_then(|id| self.risk.exposures.get(&id))
+            .filter(|e| e.account == account);
+        if let Some(e) = replaced {
+            match e.side {
+                Side::Buy => open.buy_quantity -= e.quantity,
+                Side::Sell => open.sell_quantity -= e.quantity,
+            }
+            open.notional -= e.notional;
+        }
+        // Clamped totals still fail the overflow check on the next order
+        let clamp = |units: i128, scale: u8| {
+            Decimal::new(units.clamp(i64::MIN.into(), i64::MAX.into()) as i64, scale)
+        };
+        AccountRisk {
+            account,
+            position: self.position(account),
+            open_buy_quantity: clamp(open.buy_quantity, self.quantity_scale),
+            open_sell_quantity: clamp(open.sell_quantity, self.quantity_scale),
+            open_notional: clamp(open.notional, self.price_scale),
+            limits: self.risk.limits.get(&account).copied(),
+        }
+    }
+
+    /// Bring an order's share of its account's open totals in line with the
+    /// order as it now rests or waits as a stop. Called wherever an order is
+    /// added, changed or removed.
+    pub(super) fn sync_exposure(&mut self, order_id: u32) {
+        let units = |value: Option<Decimal>, scale: u8| {
+            value
+                .and_then(|v| v.round_to(scale))
+                .map_or(0, |v| i128::from(v.units()))
+        };
+        let current = self
+            .resting_order(order_id)
+            .or_else(|| self.stops.get(order_id))
+            .and_then(|order| {
+                Some(Exposure {
+                    account: order.owner?,
+                    side: order.side,
+                    quantity: units(Some(order.total_quantity()), self.quantity_scale),
+                    notional: units(self.notional(order), self.price_scale),
+                })
+            });
+        if let Some(old) = self.risk.exposures.remove(&order_id) {
+            self.risk.apply(old, -1);
+        }
+        if let Some(new) = current {
+            self.risk.apply(new, 1);
+            self.risk.exposures.insert(order_id, new);
+        }
+    }
+
+    /// Resync the makers a match pass filled or cancelled
+    pub(super) fn sync_matched(&mut self, report: &MatchReport) {
+        for trade in &report.matches {
+            self.sync_exposure(trade.buy_order_id);
+            self.sync_exposure(trade.sell_order_id);
+        }
+        for cancel in &report.stp_cancelled {
+            self.sync_exposure(cancel.order_id);
+        }
+    }
+
+    /// Reject `order` if it would take its account past a limit. An order
+    /// already in the book with the same id is treated as being replaced.
+    /// Totals that overflow are rejected rather than clamped, since a
+    /// clamped total could pass a limit it should not.
+    pub(super) fn check_risk(&self, order: &Order) -> Result<(), RejectReason> {
+        let Some(account) = order.owner else {
+            return Ok(());
+        };
+        let Some(limits) = self.risk.limits.get(&account) else {
+            return Ok(());
+        };
+        let risk = self.account_risk(account, Some(order.id));
+        let quantity = order.total_quantity();
+
+        if let Some(limit) = limits.max_open_notional {
+            let open_notional = self
+                .notional(order)
+                .and_then(|n| risk.open_notional.checked_add(n))
+                .ok_or(RejectReason::Overflow)?;
+            if open_notional > limit {
+                return Err(RejectReason::OpenNotionalLimit {
+                    account,
+                    limit,
+                    open_notional,
+                });
+            }
+        }
+        if let Some(limit) = limits.max_position {
+            let position = match order.side {
+                Side::Buy => risk
+                    .position
+                    .checked_add(risk.open_buy_quantity)
+                    .and_then(|p| p.checked_add(quantity)),
+                Side::Sell => risk
+                    .position
+                    .checked_sub(risk.open_sell_quantity)
+                    .and_then(|p| p.checked_sub(quantity)),
+            }
+            .ok_or(RejectReason::Overflow)?;
+            if position.abs() > limit && position.abs() > risk.position.abs() {
+                return Err(RejectReason::PositionLimit {
+                    account,
+                    limit,
+                    position,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Update net positions from fills
+    pub(super) fn record_positions(&mut self, matches: &[Match]) {
+        for trade in matches {
+            for (owner, quantity) in [
+                (trade.buy_owner, trade.quantity),
+                (trade.sell_owner, -trade.quantity),
+            ] {
+                if let Some(owner) = owner {
+                    let position = self.risk.positions.entry(owner).or_default();
+                    *position = position.saturating_add(quantity);
+                }
+            }
+        }
+    }
+
+    fn position(&self, account: u32) -> Decimal {
+        self.risk
+            .positions
+            .get(&account)
+            .copied()
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Notional an order can leave resting; orders that never rest carry
+    /// none. `None` if it does not fit the book's precision.
+    fn notional(&self, order: &Order) -> Option<Decimal> {
+        if order.order_type != OrderType::Limit {
+            return Some(Decimal::ZERO);
+        }
+        order
+            .price
+            .abs()
+            .mul_round(order.total_quantity(), self.price_scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn owned(id: u32, owner: u32, side: Side, price: &str, qty: &str) -> Order {
+        Order {
+            owner: Some(owner),
+            ..Order::new(id, side, dec(price), dec(qty), id as u64)
+        }
+    }
+
+    #[test]
+    fn test_open_notional_limit_counts_resting_orders() {
+        let mut book = OrderBook::new();
+        let limits = RiskLimits {
+            max_open_notional: Some(dec("1000")),
+            ..RiskLimits::default()
+        };
+        book.set_limits(7, Some(limits)).unwrap();
+        book.submit(owned(1, 7, Side::Buy, "100", "6")).unwrap();
+
+        let err = book.submit(owned(2, 7, Side::Buy, "100", "5")).err();
+        assert_eq!(
+            err,
+            Some(RejectReason::OpenNotionalLimit {
+                account: 7,
+                limit: dec("1000"),
+                open_notional: dec("1100"),
+            })
+        );
+        // Other accounts and unowned orders are unaffected
+        book.submit(owned(3, 8, Side::Buy, "100", "50")).unwrap();
+        book.submit(Order::new(4, Side::Buy, dec("100"), dec("50"), 4))
+            .unwrap();
+        // Amending the resting order up to the limit replaces its exposure
+        assert_eq!(book.amend(1, 100.0, 10.0), Ok(true));
+        assert!(book.amend(1, 100.0, 11.0).is_err());
+        // A notional past the book's precision is refused, not clamped
+        let err = book.submit(owned(5, 7, Side::Buy, "1000000000", "1000000000"));
+        assert_eq!(err.err(), Some(RejectReason::Overflow));
+    }
+
+    #[test]
+    fn test_position_limit_uses_fills_and_open_orders() {
+        let mut book = OrderBook::new();
+        let limits = RiskLimits {
+            max_position: Some(dec("10")),
+            ..RiskLimits::default()
+        };
+        book.set_limits(7, Some(limits)).unwrap();
+        book.submit(owned(1, 9, Side::Sell, "100", "8")).unwrap();
+        book.submit(owned(2, 7, Side::Buy, "100", "8")).unwrap();
+        assert_eq!(book.account_risk(7, None).position, dec("8"));
+        assert_eq!(book.account_risk(9, None).position, dec("-8"));
+
+        book.submit(owned(3, 7, Side::Buy, "99", "1")).unwrap();
+        let err = book.submit(owned(4, 7, Side::Buy, "99", "2")).err();
+        assert_eq!(
+            err,
+            Some(RejectReason::PositionLimit {
+                account: 7,
+                limit: dec("10"),
+                position: dec("11"),
+            })
+        );
+        // Selling reduces the position, so it is always allowed up to the short limit
+        book.submit(owned(5, 7, Side::Sell, "101", "18")).unwrap();
+        assert!(book.submit(owned(6, 7, Side::Sell, "101", "1")).is_err());
+
+        book.clear_account_limits(7);
+        book.submit(owned(6, 7, Side::Sell, "101", "1")).unwrap();
+    }
+
+    #[test]
+    fn test_running_totals_follow_fills_cancels_and_amends() {
+        let mut book = OrderBook::new();
+        // The totals a full scan of the book would give
+        let scanned = |book: &OrderBook, account: u32| {
+            let mut totals = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+            let open = book.bids.values().chain(book.asks.values()).flatten();
+            for order in open
+                .chain(book.stops.orders())
+                .filter(|o| o.owner == Some(account))
+            {
+                match order.side {
+                    Side::Buy => totals.0 += order.total_quantity(),
+                    Side::Sell => totals.1 += order.total_quantity(),
+                }
+                totals.2 += book.notional(order).unwrap();
+            }
+            totals
+        };
+        let check = |book: &OrderBook| {
+            for account in [7, 8] {
+                let risk = book.account_risk(account, None);
+                assert_eq!(
+                    (
+                        risk.open_buy_quantity,
+                        risk.open_sell_quantity,
+                        risk.open_notional
+                    ),
+                    scanned(book, account)
+                );
+            }
+        };
+
+        book.submit(Order {
+            display_quantity: Some(dec("2")),
+            ..owned(1, 7, Side::Sell, "100", "10")
+        })
+        .unwrap();
+        book.submit(owned(2, 7, Side::Buy, "90", "5")).unwrap();
+        book.submit(Order {
+            stop_price: Some(dec("95")),
+            ..owned(3, 8, Side::Sell, "94", "4")
+        })
+        .unwrap();
+        check(&book);
+        assert_eq!(book.account_risk(7, None).open_notional, dec("1450"));
+
+        // Fills across the iceberg's refreshes
+        book.submit(owned(4, 8, Side::Buy, "100", "5")).unwrap();
+        check(&book);
+        assert_eq!(book.account_risk(7, None).open_sell_quantity, dec("5"));
+        assert_eq!(book.amend(2, 91.0, 3.0), Ok(true));
+        assert_eq!(book.amend(2, 91.0, 2.0), Ok(true));
+        check(&book);
+        assert!(book.cancel(1));
+        assert!(book.cancel(3));
+        check(&book);
+        assert_eq!(book.account_risk(8, None).open_notional, Decimal::ZERO);
+    }
+}
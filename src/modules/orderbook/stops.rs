@@ -0,0 +1,95 @@
+//! Stop Orders
+//!
+//! Trigger book holding stop and stop-limit orders out of the visible book
+//! until the last trade price reaches their stop price. A buy stop fires when
+//! the price rises to or through its stop, a sell stop when it falls to or
+//! through it.
+
+use super::super::decimal::Decimal;
+use super::{remove_from_level, Order, Price, ReversePrice, Side};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct StopBook {
+    // Lowest buy stop fires first as the price rises
+    buys: BTreeMap<Price, Vec<Order>>,
+    // Highest sell stop fires first as the price falls
+    sells: BTreeMap<ReversePrice, Vec<Order>>,
+    index: HashMap<u32, (Side, i64)>,
+}
+
+impl StopBook {
+    /// Park an order whose `stop_price` is set
+    pub(super) fn insert(&mut self, order: Order) {
+        let Some(stop) = order.stop_price else {
+            return;
+        };
+        let key = stop.units();
+        match order.side {
+            Side::Buy => self.buys.entry(Price(key)).or_default().push(order),
+            Side::Sell => self.sells.entry(ReversePrice(key)).or_default().push(order),
+        }
+        self.index.insert(order.id, (order.side, key));
+    }
+
+    pub(super) fn remove(&mut self, order_id: u32) -> Option<Order> {
+        let (side, key) = self.index.remove(&order_id)?;
+        match side {
+            Side::Buy => remove_from_level(&mut self.buys, Price(key), order_id),
+            Side::Sell => remove_from_level(&mut self.sells, ReversePrice(key), order_id),
+        }
+    }
+
+    pub(super) fn get(&self, order_id: u32) -> Option<&Order> {
+        let &(side, key) = self.index.get(&order_id)?;
+        let level = match side {
+            Side::Buy => self.buys.get(&Price(key)),
+            Side::Sell => self.sells.get(&ReversePrice(key)),
+        };
+        level?.iter().find(|o| o.id == order_id)
+    }
+
+    pub(super) fn contains(&self, order_id: u32) -> bool {
+        self.index.contains_key(&order_id)
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Every parked order, buys then sells, each in trigger order
+    pub(super) fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.buys.values().chain(self.sells.values()).flatten()
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.buys.clear();
+        self.sells.clear();
+        self.index.clear();
+    }
+
+    /// Remove every stop triggered by a trade at `last`, in arrival order
+    pub(super) fn take_triggered(&mut self, last: Decimal) -> Vec<Order> {
+        let mut fired = Vec::new();
+        take_levels(&mut self.buys, last, &mut fired);
+        take_levels(&mut self.sells, last, &mut fired);
+        for order in &fired {
+            self.index.remove(&order.id);
+        }
+        fired.sort_by_key(|o| o.sequence);
+        fired
+    }
+}
+
+fn take_levels<K: Ord>(
+    levels: &mut BTreeMap<K, Vec<Order>>,
+    last: Decimal,
+    fired: &mut Vec<Order>,
+) {
+    while let Some(entry) = levels.first_entry() {
+        if !entry.get().first().is_some_and(|o| o.triggers(last)) {
+            break;
+        }
+        fired.extend(entry.remove());
+    }
+}
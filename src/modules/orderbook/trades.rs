@@ -0,0 +1,305 @@
+//! Trade History and Candles
+//!
+//! Keeps a bounded tape of recent fills, rolling 24h volume and VWAP, and
+//! OHLCV candles at configurable intervals. Timestamps are the aggressing
+//! order's timestamp in milliseconds.
+
+use super::super::decimal::Decimal;
+use super::Match;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const MINUTE_MS: u64 = 60_000;
+const DAY_MS: u64 = 24 * 60 * MINUTE_MS;
+
+/// Default number of trades kept on the tape
+pub const DEFAULT_TAPE_LIMIT: usize = 1000;
+/// Candles retained per interval
+pub const MAX_CANDLES: usize = 1000;
+/// Default candle intervals: 1m, 15m and 1h
+pub const DEFAULT_CANDLE_INTERVALS: [u64; 3] = [MINUTE_MS, 15 * MINUTE_MS, 60 * MINUTE_MS];
+
+/// OHLCV bar starting at `start` (inclusive)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    pub start: u64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    pub trade_count: u32,
+}
+
+/// Trading activity summary as of a point in time
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TradeStats {
+    pub last_price: Option<Decimal>,
+    /// Volume-weighted average price over the last 24h
+    pub vwap_24h: Option<Decimal>,
+    pub volume_24h: Decimal,
+    pub trade_count_24h: u32,
+}
+
+// Per-minute totals backing the rolling 24h figures
+#[derive(Clone, Copy, Debug)]
+struct MinuteBucket {
+    start: u64,
+    volume: Decimal,
+    notional: Decimal,
+    trade_count: u32,
+}
+
+#[derive(Clone, Debug)]
+struct CandleSeries {
+    interval: u64,
+    candles: VecDeque<Candle>,
+}
+
+impl CandleSeries {
+    fn new(interval: u64) -> Self {
+        Self {
+            interval,
+            candles: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, trade: &Match) {
+        let start = trade.timestamp - trade.timestamp % self.interval;
+        match self.candles.back_mut() {
+            // Late trades are folded into the current bar rather than reopening an old one
+            Some(candle) if start <= candle.start => {
+                candle.high = candle.high.max(trade.price);
+                candle.low = candle.low.min(trade.price);
+                candle.close = trade.price;
+                candle.volume = candle.volume.saturating_add(trade.quantity);
+                candle.trade_count += 1;
+            }
+            _ => {
+                self.candles.push_back(Candle {
+                    start,
+                    open: trade.price,
+                    high: trade.price,
+                    low: trade.price,
+                    close: trade.price,
+                    volume: trade.quantity,
+                    trade_count: 1,
+                });
+                if self.candles.len() > MAX_CANDLES {
+                    self.candles.pop_front();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct TradeHistory {
+    tape: VecDeque<Match>,
+    tape_limit: usize,
+    minutes: VecDeque<MinuteBucket>,
+    series: Vec<CandleSeries>,
+    last_price: Option<Decimal>,
+}
+
+impl Default for TradeHistory {
+    fn default() -> Self {
+        Self {
+            tape: VecDeque::new(),
+            tape_limit: DEFAULT_TAPE_LIMIT,
+            minutes: VecDeque::new(),
+            series: DEFAULT_CANDLE_INTERVALS
+                .iter()
+                .map(|&interval| CandleSeries::new(interval))
+                .collect(),
+            last_price: None,
+        }
+    }
+}
+
+impl TradeHistory {
+    pub(super) fn last_price(&self) -> Option<Decimal> {
+        self.last_price
+    }
+
+    /// Seed the last price when restoring a book without its tape
+    pub(super) fn set_last_price(&mut self, price: Option<Decimal>) {
+        self.last_price = price;
+    }
+
+    /// Add a fill; `price_scale` is the precision notional is kept at
+    pub(super) fn record(&mut self, trade: &Match, price_scale: u8) {
+        self.last_price = Some(trade.price);
+
+        self.tape.push_back(*trade);
+        while self.tape.len() > self.tape_limit {
+            self.tape.pop_front();
+        }
+
+        let notional = trade
+            .price
+            .mul_round(trade.quantity, price_scale)
+            .unwrap_or(Decimal::ZERO);
+        let start = trade.timestamp - trade.timestamp % MINUTE_MS;
+        match self.minutes.back_mut() {
+            Some(bucket) if start <= bucket.start => {
+                bucket.volume = bucket.volume.saturating_add(trade.quantity);
+                bucket.notional = bucket.notional.saturating_add(notional);
+                bucket.trade_count += 1;
+            }
+            _ => self.minutes.push_back(MinuteBucket {
+                start,
+                volume: trade.quantity,
+                notional,
+                trade_count: 1,
+            }),
+        }
+        while self
+            .minutes
+            .front()
+            .is_some_and(|b| b.start + DAY_MS <= start)
+        {
+            self.minutes.pop_front();
+        }
+
+        for series in &mut self.series {
+            series.record(trade);
+        }
+    }
+
+    /// Most recent `limit` trades, oldest first
+    pub(super) fn recent(&self, limit: usize) -> Vec<Match> {
+        let skip = self.tape.len().saturating_sub(limit);
+        self.tape.iter().skip(skip).copied().collect()
+    }
+
+    /// Totals over the 24h ending at `now`, to minute resolution
+    pub(super) fn stats(&self, now: u64, price_scale: u8) -> TradeStats {
+        let since = now.saturating_sub(DAY_MS);
+        let mut volume = Decimal::ZERO;
+        let mut notional = Decimal::ZERO;
+        let mut trade_count = 0;
+        for bucket in self
+            .minutes
+            .iter()
+            .filter(|b| b.start + MINUTE_MS > since && b.start <= now)
+        {
+            volume = volume.saturating_add(bucket.volume);
+            notional = notional.saturating_add(bucket.notional);
+            trade_count += bucket.trade_count;
+        }
+        TradeStats {
+            last_price: self.last_price,
+            vwap_24h: notional.div_round(volume, price_scale),
+            volume_24h: volume,
+            trade_count_24h: trade_count,
+        }
+    }
+
+    pub(super) fn candles(&self, interval: u64) -> Option<Vec<Candle>> {
+        self.series
+            .iter()
+            .find(|s| s.interval == interval)
+            .map(|s| s.candles.iter().copied().collect())
+    }
+
+    /// Replace the candle intervals, discarding existing bars
+    pub(super) fn set_intervals(&mut self, intervals: &[u64]) -> Result<(), String> {
+        if intervals.contains(&0) {
+            return Err("Candle intervals must be positive".to_string());
+        }
+        let mut intervals = intervals.to_vec();
+        intervals.sort_unstable();
+        intervals.dedup();
+        self.series = intervals.into_iter().map(CandleSeries::new).collect();
+        Ok(())
+    }
+
+    pub(super) fn set_tape_limit(&mut self, limit: usize) {
+        self.tape_limit = limit;
+        while self.tape.len() > limit {
+            self.tape.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(timestamp: u64, price: &str, quantity: &str) -> Match {
+        Match {
+            buy_order_id: 1,
+            sell_order_id: 2,
+            price: price.parse().unwrap(),
+            quantity: quantity.parse().unwrap(),
+            timestamp,
+            buy_owner: None,
+            sell_owner: None,
+        }
+    }
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_candles_aggregate_per_interval() {
+        let mut history = TradeHistory::default();
+        history.record(&trade(10_000, "100", "1"), 6);
+        history.record(&trade(50_000, "103", "2"), 6);
+        history.record(&trade(59_999, "99", "1"), 6);
+        history.record(&trade(61_000, "101", "4"), 6);
+
+        let minute = history.candles(MINUTE_MS).unwrap();
+        assert_eq!(minute.len(), 2);
+        assert_eq!(
+            minute[0],
+            Candle {
+                start: 0,
+                open: dec("100"),
+                high: dec("103"),
+                low: dec("99"),
+                close: dec("99"),
+                volume: dec("4"),
+                trade_count: 3,
+            }
+        );
+        assert_eq!((minute[1].start, minute[1].open), (MINUTE_MS, dec("101")));
+
+        let hour = history.candles(60 * MINUTE_MS).unwrap();
+        assert_eq!(hour.len(), 1);
+        assert_eq!((hour[0].volume, hour[0].close), (dec("8"), dec("101")));
+        assert!(history.candles(5 * MINUTE_MS).is_none());
+    }
+
+    #[test]
+    fn test_stats_cover_trailing_day() {
+        let mut history = TradeHistory::default();
+        history.record(&trade(0, "50", "10"), 6);
+        history.record(&trade(DAY_MS, "100", "1"), 6);
+        history.record(&trade(DAY_MS + 1, "103", "2"), 6);
+
+        let stats = history.stats(DAY_MS + MINUTE_MS, 6);
+        assert_eq!(stats.last_price, Some(dec("103")));
+        assert_eq!(stats.volume_24h, dec("3"));
+        assert_eq!(stats.trade_count_24h, 2);
+        assert_eq!(stats.vwap_24h, Some(dec("102")));
+
+        let quiet = history.stats(3 * DAY_MS, 6);
+        assert_eq!((quiet.volume_24h, quiet.vwap_24h), (Decimal::ZERO, None));
+    }
+
+    #[test]
+    fn test_tape_is_bounded() {
+        let mut history = TradeHistory::default();
+        history.set_tape_limit(2);
+        for ts in 1..=3 {
+            history.record(&trade(ts, "100", "1"), 6);
+        }
+        let recent: Vec<u64> = history.recent(10).iter().map(|t| t.timestamp).collect();
+        assert_eq!(recent, vec![2, 3]);
+        assert_eq!(history.recent(1)[0].timestamp, 3);
+        assert!(history.set_intervals(&[0]).is_err());
+    }
+}